@@ -0,0 +1,218 @@
+use rusqlite::{Connection, OptionalExtension};
+use structopt::StructOpt;
+
+use crate::sqlite;
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Only report the orphaned entries, without remapping them
+    #[structopt(long)]
+    dry_run: bool,
+}
+
+struct Orphan {
+    id: String,
+    owned: usize,
+    identity: Option<Identity>,
+}
+
+struct Identity {
+    /// Oracle id of the card, or its id when it has none
+    oracle: String,
+    lang: String,
+    set_code: String,
+    collector_number: String,
+}
+
+/// Tables referencing scryfall ids, along with the other columns of their primary key. Rows are
+/// merged by adding their `amount` when a remapping makes them collide.
+const REFERENCES: &[(&str, &str)] = &[
+    ("cards", "foil, owner"),
+    ("list_cards", "hash, foil"),
+    ("deck_cards", "deck, board"),
+    ("card_tags", "foil, owner, tag"),
+];
+
+/// Tables holding an optional reference to a scryfall id, along with the referencing column
+const PRINTING_REFERENCES: &[(&str, &str)] = &[
+    ("wants", "printing"),
+    ("preferred_printings", "id"),
+    ("acquisitions", "id"),
+    ("disposals", "id"),
+    ("card_notes", "id"),
+    ("loans", "id"),
+];
+
+/// Moves every reference to `old` in the collection to `new`
+fn remap(db: &Connection, old: &str, new: &str) -> rusqlite::Result<()> {
+    for (table, keys) in REFERENCES {
+        db.execute(
+            &format!(
+                r#"INSERT INTO {table} ({keys}, id, amount)
+                SELECT {keys}, ?2, amount FROM {table} WHERE id = ?1
+                ON CONFLICT ({keys}, id) DO UPDATE SET amount = amount + excluded.amount"#,
+                table = table,
+                keys = keys
+            ),
+            [old, new],
+        )?;
+        db.execute(&format!("DELETE FROM {} WHERE id = ?1", table), [old])?;
+    }
+    for (table, column) in PRINTING_REFERENCES {
+        db.execute(
+            &format!(
+                "UPDATE {table} SET {column} = ?2 WHERE {column} = ?1",
+                table = table,
+                column = column
+            ),
+            [old, new],
+        )?;
+    }
+    Ok(())
+}
+
+impl Args {
+    pub fn check(self) -> color_eyre::Result<()> {
+        let mut db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+        sqlite::create_history_table(&db)?;
+
+        let tx = db.transaction()?;
+        let referenced = REFERENCES
+            .iter()
+            .map(|(table, _)| format!("SELECT id FROM {}", table))
+            .chain(PRINTING_REFERENCES.iter().map(|(table, column)| {
+                format!(
+                    "SELECT {column} FROM {table} WHERE {column} IS NOT NULL",
+                    table = table,
+                    column = column
+                )
+            }))
+            .collect::<Vec<_>>()
+            .join(" UNION ");
+        let orphans: Vec<Orphan> = tx
+            .prepare(&format!(
+                r#"
+            SELECT
+                refs.id,
+                (SELECT IFNULL(SUM(amount), 0) FROM cards WHERE cards.id = refs.id),
+                history.id IS NOT NULL,
+                IFNULL(history.oracle_id, history.id),
+                history.lang,
+                history.set_code,
+                history.collector_number
+            FROM
+                ({}) AS refs
+                LEFT JOIN scryfall_history AS history ON history.id = refs.id
+            WHERE
+                refs.id NOT IN (SELECT id FROM scryfall)
+            ORDER BY refs.id"#,
+                referenced
+            ))?
+            .query_map([], |row| {
+                let known: bool = row.get(2)?;
+                Ok(Orphan {
+                    id: row.get(0)?,
+                    owned: row.get(1)?,
+                    identity: match known {
+                        false => None,
+                        true => Some(Identity {
+                            oracle: row.get(3)?,
+                            lang: row.get(4)?,
+                            set_code: row.get(5)?,
+                            collector_number: row.get(6)?,
+                        }),
+                    },
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        if orphans.is_empty() {
+            println!("No orphaned cards in the collection");
+            return Ok(());
+        }
+
+        let mut unresolved = Vec::new();
+        {
+            let mut replacement = tx.prepare(
+                r#"
+            SELECT id, display_name FROM scryfall
+            WHERE IFNULL(oracle_id, id) = ?1 AND lang = ?2 AND set_code = ?3 AND collector_number = ?4"#,
+            )?;
+
+            for orphan in &orphans {
+                let identity = match &orphan.identity {
+                    Some(i) => i,
+                    None => {
+                        unresolved.push((orphan, "id was never seen in a dump"));
+                        continue;
+                    }
+                };
+
+                let candidates: Vec<(String, String)> = replacement
+                    .query_map(
+                        rusqlite::params![
+                            identity.oracle,
+                            identity.lang,
+                            identity.set_code,
+                            identity.collector_number
+                        ],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )?
+                    .collect::<Result<_, _>>()?;
+
+                match candidates.as_slice() {
+                    [] => unresolved.push((orphan, "no printing left with the same identity")),
+                    [(new_id, name)] => {
+                        println!(
+                            "{} {} ({} #{}): {} -> {}",
+                            if self.dry_run {
+                                "Would remap"
+                            } else {
+                                "Remapped"
+                            },
+                            name,
+                            identity.set_code,
+                            identity.collector_number,
+                            orphan.id,
+                            new_id
+                        );
+                        if !self.dry_run {
+                            remap(&tx, &orphan.id, new_id)?;
+                        }
+                    }
+                    _ => unresolved.push((orphan, "several printings share the same identity")),
+                }
+            }
+        }
+        tx.commit()?;
+
+        if !unresolved.is_empty() {
+            println!("Could not fix {} entries:", unresolved.len());
+            for (orphan, reason) in unresolved {
+                let name: Option<String> = db
+                    .query_row(
+                        r#"SELECT scryfall.display_name FROM scryfall_history, scryfall
+                        WHERE scryfall_history.id = ?1
+                            AND IFNULL(scryfall.oracle_id, scryfall.id)
+                                = IFNULL(scryfall_history.oracle_id, scryfall_history.id)
+                        LIMIT 1"#,
+                        [&orphan.id],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                println!(
+                    "  - {} ({} owned){}: {}",
+                    orphan.id,
+                    orphan.owned,
+                    name.map(|n| format!(" ({})", n)).unwrap_or_default(),
+                    reason
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+use color_eyre::eyre::eyre;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::price::Currency;
+
+/// Price and date of copies entering or leaving the collection
+#[derive(Clone, Copy, Debug)]
+pub struct Deal<'a> {
+    /// Price of each copy
+    pub price: Option<f64>,
+    pub currency: Currency,
+    /// Today when not given
+    pub date: Option<&'a str>,
+    /// Sale, trade or removal the copies are part of
+    pub exchange: Option<i64>,
+}
+
+impl Deal<'_> {
+    pub fn unknown() -> Self {
+        Self {
+            price: None,
+            currency: Currency::Eur,
+            date: None,
+            exchange: None,
+        }
+    }
+}
+
+/// Records a sale, trade or removal, returning its id
+pub fn record_exchange(
+    db: &Connection,
+    kind: &str,
+    counterparty: Option<&str>,
+    reason: Option<&str>,
+    cash: Option<f64>,
+    deal: Deal,
+) -> rusqlite::Result<i64> {
+    db.execute(
+        r#"INSERT INTO exchanges (kind, counterparty, reason, cash, currency, date)
+        VALUES (?1, ?2, ?3, ?4, ?5, IFNULL(?6, date('now')))"#,
+        rusqlite::params![
+            kind,
+            counterparty,
+            reason,
+            cash,
+            deal.currency.as_str(),
+            deal.date
+        ],
+    )?;
+    Ok(db.last_insert_rowid())
+}
+
+/// Checks that a date is written as `YYYY-MM-DD`
+pub fn is_date(date: &str) -> bool {
+    date.len() == 10
+        && date.char_indices().all(|(i, c)| match i {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+pub fn parse_date(date: &str) -> color_eyre::Result<String> {
+    match is_date(date) {
+        true => Ok(date.into()),
+        false => Err(eyre!("{} is not a date written as YYYY-MM-DD", date)),
+    }
+}
+
+/// Current price of a printing, if known
+pub fn market_price(
+    db: &Connection,
+    id: &str,
+    foil: bool,
+    currency: Currency,
+) -> rusqlite::Result<Option<f64>> {
+    db.query_row(
+        &format!(
+            "SELECT {} FROM scryfall WHERE id = ?1",
+            currency.price_expr("?2")
+        ),
+        rusqlite::params![id, foil],
+        |row| row.get(0),
+    )
+    .optional()
+    .map(Option::flatten)
+}
+
+/// Shares `total` between the copies of `cards`, given as `(id, foil, count)`, according to their
+/// current price. Returns the price of each copy of every card.
+pub fn share_price(
+    db: &Connection,
+    total: f64,
+    currency: Currency,
+    cards: &[(&str, bool, usize)],
+) -> rusqlite::Result<Vec<f64>> {
+    let prices = cards
+        .iter()
+        .map(|&(id, foil, _)| Ok(market_price(db, id, foil, currency)?.unwrap_or(0.)))
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let worth: f64 = prices
+        .iter()
+        .zip(cards)
+        .map(|(price, (_, _, count))| price * *count as f64)
+        .sum();
+    let copies: usize = cards.iter().map(|(_, _, count)| count).sum();
+
+    Ok(prices
+        .into_iter()
+        .map(|price| match worth > 0. {
+            true => total * price / worth,
+            // Nothing has a price, every copy costs the same
+            false => total / copies as f64,
+        })
+        .collect())
+}
+
+/// Name shown for the cards added without an owner
+pub fn owner_name(owner: &str) -> &str {
+    match owner {
+        "" => "(no owner)",
+        owner => owner,
+    }
+}
+
+/// Adds copies of a printing to the collection of `owner`, bought through the list with the hash
+/// `list`
+pub fn add_cards(
+    db: &Connection,
+    id: &str,
+    foil: bool,
+    amount: usize,
+    owner: &str,
+    list: Option<&[u8]>,
+    deal: Deal,
+) -> rusqlite::Result<()> {
+    let is_present: usize = db.query_row(
+        "SELECT COUNT(*) FROM cards WHERE id = ?1 AND foil = ?2 AND owner = ?3",
+        rusqlite::params![id, foil, owner],
+        |row| row.get(0),
+    )?;
+    if is_present == 0 {
+        db.execute(
+            "INSERT INTO cards (id, foil, owner, amount) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![id, foil, owner, amount],
+        )?;
+    } else {
+        db.execute(
+            "UPDATE cards SET amount = amount + ?4 WHERE id = ?1 AND foil = ?2 AND owner = ?3",
+            rusqlite::params![id, foil, owner, amount],
+        )?;
+    }
+    db.execute(
+        r#"INSERT INTO acquisitions (id, foil, owner, hash, amount, bought, price, currency, date, exchange)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6, ?7, IFNULL(?8, date('now')), ?9)"#,
+        rusqlite::params![
+            id,
+            foil,
+            owner,
+            list,
+            amount,
+            deal.price,
+            deal.currency.as_str(),
+            deal.date,
+            deal.exchange
+        ],
+    )?;
+    Ok(())
+}
+
+/// Removes copies of a printing from the collection of `owner`, failing if not enough of them
+/// are owned and at hand.
+///
+/// The oldest acquisitions are removed first, and the gains made on them are recorded. Without a
+/// price in `deal` the copies were not sold, and are recorded without a price.
+pub fn remove_cards(
+    db: &Connection,
+    id: &str,
+    foil: bool,
+    amount: usize,
+    owner: &str,
+    deal: Deal,
+) -> color_eyre::Result<()> {
+    let owned: usize = db.query_row(
+        "SELECT IFNULL(SUM(amount), 0) FROM cards WHERE id = ?1 AND foil = ?2 AND owner = ?3",
+        rusqlite::params![id, foil, owner],
+        |row| row.get(0),
+    )?;
+    // Lent copies are not at hand, they have to be returned first
+    let lent: usize = db.query_row(
+        r#"SELECT IFNULL(SUM(amount), 0) FROM loans
+        WHERE id = ?1 AND foil = ?2 AND owner = ?3 AND returned IS NULL"#,
+        rusqlite::params![id, foil, owner],
+        |row| row.get(0),
+    )?;
+    if owned.saturating_sub(lent) < amount {
+        return Err(eyre!(
+            "cannot remove {} copies of {}{}, only {} are owned{}{}",
+            amount,
+            id,
+            if foil { " [F]" } else { "" },
+            owned,
+            match owner {
+                "" => String::new(),
+                owner => format!(" by {}", owner),
+            },
+            match lent {
+                0 => String::new(),
+                lent => format!(" and {} of them are lent", lent),
+            }
+        ));
+    }
+
+    db.execute(
+        "UPDATE cards SET amount = amount - ?4 WHERE id = ?1 AND foil = ?2 AND owner = ?3",
+        rusqlite::params![id, foil, owner, amount],
+    )?;
+    db.execute("DELETE FROM cards WHERE amount = 0", [])?;
+    clamp_tags(db, id, foil, owner)?;
+    db.execute(
+        r#"DELETE FROM card_notes WHERE id = ?1 AND foil = ?2 AND owner = ?3
+        AND NOT EXISTS (
+            SELECT 1 FROM cards WHERE cards.id = ?1 AND cards.foil = ?2 AND cards.owner = ?3
+        )"#,
+        rusqlite::params![id, foil, owner],
+    )?;
+
+    let mut lots = db
+        .prepare(
+            r#"
+            SELECT acquisition, amount, currency FROM acquisitions
+            WHERE id = ?1 AND foil = ?2 AND owner = ?3 AND amount > 0
+            ORDER BY date, acquisition"#,
+        )?
+        .query_map(rusqlite::params![id, foil, owner], |row| {
+            Ok((Some(row.get(0)?), row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<(Option<i64>, usize, String)>, _>>()?;
+    // Copies owned before their price was tracked
+    lots.push((None, usize::MAX, deal.currency.as_str().into()));
+
+    let mut left = amount;
+    for (lot, available, currency) in lots {
+        if left == 0 {
+            break;
+        }
+        let taken = left.min(available);
+        left -= taken;
+
+        // Copies removed without a price were not sold, they are lost or thrown away
+        let currency = match deal.price {
+            Some(_) => deal.currency,
+            None => currency.parse()?,
+        };
+        db.execute(
+            "UPDATE acquisitions SET amount = amount - ?2 WHERE acquisition = ?1",
+            rusqlite::params![lot, taken],
+        )?;
+        db.execute(
+            r#"INSERT INTO disposals (acquisition, id, foil, amount, price, currency, date, exchange)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, IFNULL(?7, date('now')), ?8)"#,
+            rusqlite::params![
+                lot,
+                id,
+                foil,
+                taken,
+                deal.price,
+                currency.as_str(),
+                deal.date,
+                deal.exchange
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Makes sure no more copies of a printing are tagged than its owner has
+fn clamp_tags(db: &Connection, id: &str, foil: bool, owner: &str) -> rusqlite::Result<()> {
+    db.execute(
+        r#"UPDATE card_tags SET amount = MIN(amount, (
+            SELECT IFNULL(SUM(amount), 0) FROM cards
+            WHERE cards.id = ?1 AND cards.foil = ?2 AND cards.owner = ?3
+        ))
+        WHERE id = ?1 AND foil = ?2 AND owner = ?3"#,
+        rusqlite::params![id, foil, owner],
+    )?;
+    db.execute("DELETE FROM card_tags WHERE amount <= 0", [])?;
+    Ok(())
+}
+
+/// Tags copies of an owned printing, at most as many as the owner has
+pub fn tag_cards<T: AsRef<str>>(
+    db: &Connection,
+    id: &str,
+    foil: bool,
+    amount: usize,
+    owner: &str,
+    tags: &[T],
+) -> rusqlite::Result<()> {
+    for tag in tags {
+        db.execute(
+            r#"INSERT INTO card_tags (id, foil, owner, tag, amount) VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (id, foil, owner, tag) DO UPDATE SET amount = amount + excluded.amount"#,
+            rusqlite::params![id, foil, owner, tag.as_ref(), amount],
+        )?;
+    }
+    clamp_tags(db, id, foil, owner)
+}
+
+/// Removes a tag from copies of a printing, from all of them without an amount. Returns the
+/// number of copies that were tagged.
+pub fn untag_cards(
+    db: &Connection,
+    id: &str,
+    foil: bool,
+    amount: Option<usize>,
+    owner: &str,
+    tag: &str,
+) -> rusqlite::Result<usize> {
+    let tagged: usize = db
+        .query_row(
+            "SELECT amount FROM card_tags WHERE id = ?1 AND foil = ?2 AND owner = ?3 AND tag = ?4",
+            rusqlite::params![id, foil, owner, tag],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0);
+    db.execute(
+        r#"UPDATE card_tags SET amount = amount - ?5
+        WHERE id = ?1 AND foil = ?2 AND owner = ?3 AND tag = ?4"#,
+        rusqlite::params![id, foil, owner, tag, amount.unwrap_or(tagged).min(tagged)],
+    )?;
+    db.execute("DELETE FROM card_tags WHERE amount <= 0", [])?;
+    Ok(tagged)
+}
@@ -0,0 +1,619 @@
+use color_eyre::eyre::{eyre, WrapErr};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use crate::{
+    filter::PrintingFilter,
+    legality,
+    price::Currency,
+    resolve::{Board, Line, Resolver},
+    sqlite,
+};
+
+#[derive(StructOpt)]
+pub enum Args {
+    /// Save a decklist, written in any format accepted by add-list
+    Add(AddArgs),
+    /// Show the saved decks
+    List(ListArgs),
+    /// Delete a saved deck
+    Remove(DeckArgs),
+    /// Check which cards of a deck are owned, and what the missing ones cost
+    Check(CheckArgs),
+    /// List the tokens and emblems created by the cards of the decks, and whether they are owned
+    Tokens(TokensArgs),
+    /// Check that a saved deck, or a list, follows the rules of its format
+    Validate(ValidateArgs),
+}
+
+#[derive(StructOpt)]
+pub struct AddArgs {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    name: String,
+    list: PathBuf,
+    /// Format the deck is played in (commander, modern, ...)
+    #[structopt(long, short)]
+    format: Option<String>,
+    /// Replace the deck if it already exists
+    #[structopt(long)]
+    replace: bool,
+    /// Neither use nor remember the printings chosen in previous runs
+    #[structopt(long)]
+    ignore_prefs: bool,
+}
+
+#[derive(StructOpt)]
+pub struct ListArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+}
+
+#[derive(StructOpt)]
+pub struct DeckArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    name: String,
+}
+
+#[derive(StructOpt)]
+pub struct CheckArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    name: String,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+    /// Only count the copies of this owner, and the ones they lent
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    owner: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct ValidateArgs {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    /// Name of a saved deck, or a list in any format accepted by add-list
+    deck: String,
+    /// Format to check the deck against, the format of the saved deck by default
+    #[structopt(long, short, possible_values = legality::FORMATS)]
+    format: Option<String>,
+    /// Neither use nor remember the printings chosen in previous runs
+    #[structopt(long)]
+    ignore_prefs: bool,
+}
+
+#[derive(StructOpt)]
+pub struct TokensArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Only show the tokens of this deck
+    name: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeckSummary {
+    pub name: String,
+    pub format: Option<String>,
+    /// Cards outside of the sideboard
+    pub main: usize,
+    pub side: usize,
+}
+
+pub fn decks(db: &Connection) -> rusqlite::Result<Vec<DeckSummary>> {
+    let decks = db
+        .prepare(
+            r#"
+            SELECT
+                decks.name,
+                decks.format,
+                IFNULL(SUM(IIF(deck_cards.board = 'side', 0, deck_cards.amount)), 0),
+                IFNULL(SUM(IIF(deck_cards.board = 'side', deck_cards.amount, 0)), 0)
+            FROM decks LEFT JOIN deck_cards ON deck_cards.deck = decks.name
+            GROUP BY decks.name
+            ORDER BY decks.name"#,
+        )?
+        .query_map([], |row| {
+            Ok(DeckSummary {
+                name: row.get(0)?,
+                format: row.get(1)?,
+                main: row.get(2)?,
+                side: row.get(3)?,
+            })
+        })?
+        .collect();
+    decks
+}
+
+/// Ownership of a card of a deck, all printings of the card taken together
+#[derive(Serialize, Debug)]
+pub struct CardStatus {
+    /// Oracle id, or printing id for the cards missing from the database
+    pub oracle: String,
+    pub name: String,
+    /// The printing is missing from the card database
+    pub unknown: bool,
+    pub needed: usize,
+    pub owned: usize,
+    /// Copies used by the other decks
+    pub committed: Vec<(String, usize)>,
+    /// Copies lent and not returned yet, by borrower
+    pub lent: Vec<(String, usize)>,
+    /// Price of the cheapest printing
+    pub price: Option<f64>,
+}
+
+impl CardStatus {
+    /// Copies to get, the lent ones not being at hand
+    pub fn missing(&self) -> usize {
+        self.needed
+            .saturating_sub(self.owned.saturating_sub(self.lent()))
+    }
+
+    fn lent(&self) -> usize {
+        self.lent.iter().map(|(_, amount)| amount).sum()
+    }
+
+    /// Owned copies that are neither used by another deck nor lent
+    pub fn available(&self) -> usize {
+        let committed: usize = self.committed.iter().map(|(_, amount)| amount).sum();
+        self.owned.saturating_sub(committed + self.lent())
+    }
+}
+
+pub fn deck_exists(db: &Connection, name: &str) -> color_eyre::Result<()> {
+    let exists: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM decks WHERE name = ?1",
+        [name],
+        |row| row.get(0),
+    )?;
+    match exists {
+        true => Ok(()),
+        false => Err(eyre!("there is no deck named {}", name)),
+    }
+}
+
+/// A token or emblem created by cards of a deck
+#[derive(Serialize, Debug)]
+pub struct Token {
+    pub name: String,
+    pub type_line: Option<String>,
+    /// Cards of the deck creating it
+    pub made_by: Vec<String>,
+    /// Owned copies, all printings of the token taken together
+    pub owned: usize,
+}
+
+/// Finds the tokens created by the cards of a deck
+pub fn deck_tokens(db: &Connection, deck: &str) -> color_eyre::Result<Vec<Token>> {
+    deck_exists(db, deck)?;
+
+    let tokens = db
+        .prepare(
+            r#"
+            WITH sources AS (
+                SELECT DISTINCT
+                    IFNULL(token.oracle_id, scryfall_related.related_id) AS oracle,
+                    scryfall_related.name AS name,
+                    scryfall_related.type_line AS type_line,
+                    source.display_name AS made_by
+                FROM
+                    deck_cards
+                    JOIN scryfall AS source ON source.id = deck_cards.id
+                    JOIN scryfall_related
+                        ON scryfall_related.id = source.id AND scryfall_related.component = 'token'
+                    LEFT JOIN scryfall AS token ON token.id = scryfall_related.related_id
+                WHERE deck_cards.deck = ?1
+            )
+            -- Names may hold commas, they are joined with a control character instead
+            SELECT
+                sources.oracle,
+                MIN(sources.name) AS name,
+                MIN(sources.type_line),
+                group_concat(sources.made_by, char(31)),
+                (
+                    SELECT IFNULL(SUM(cards.amount), 0)
+                    FROM cards JOIN scryfall ON scryfall.id = cards.id
+                    WHERE scryfall.oracle_id = sources.oracle OR scryfall.id = sources.oracle
+                )
+            FROM sources
+            GROUP BY sources.oracle
+            ORDER BY name"#,
+        )?
+        .query_map([deck], |row| {
+            let made_by: String = row.get(3)?;
+            Ok(Token {
+                name: row.get(1)?,
+                type_line: row.get(2)?,
+                made_by: made_by.split('\u{1f}').map(Into::into).collect(),
+                owned: row.get(4)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+
+    Ok(tokens)
+}
+
+/// Computes the ownership of every card of a deck, by `owner` or by everyone taken together
+pub fn check_deck(
+    db: &Connection,
+    deck: &str,
+    owner: Option<&str>,
+    currency: Currency,
+) -> color_eyre::Result<Vec<CardStatus>> {
+    deck_exists(db, deck)?;
+
+    let mut committed = db.prepare(
+        r#"
+        SELECT deck_cards.deck, SUM(deck_cards.amount)
+        FROM deck_cards LEFT JOIN scryfall ON scryfall.id = deck_cards.id
+        WHERE deck_cards.deck != ?1 AND (scryfall.oracle_id = ?2 OR deck_cards.id = ?2)
+        GROUP BY deck_cards.deck
+        ORDER BY deck_cards.deck"#,
+    )?;
+    let mut lent = db.prepare(
+        r#"
+        SELECT loans.borrower, SUM(loans.amount)
+        FROM loans LEFT JOIN scryfall ON scryfall.id = loans.id
+        WHERE loans.returned IS NULL AND (scryfall.oracle_id = ?1 OR loans.id = ?1)
+            AND (?2 IS NULL OR loans.owner = ?2)
+        GROUP BY loans.borrower
+        ORDER BY loans.borrower"#,
+    )?;
+
+    let cards = db
+        .prepare(&format!(
+            r#"
+        WITH deck AS (
+            SELECT
+                IFNULL(scryfall.oracle_id, deck_cards.id) AS oracle,
+                MIN(IFNULL(scryfall.display_name, 'unknown card ' || deck_cards.id)) AS name,
+                MIN(scryfall.id IS NULL) AS unknown,
+                SUM(deck_cards.amount) AS needed
+            FROM deck_cards LEFT JOIN scryfall ON scryfall.id = deck_cards.id
+            WHERE deck_cards.deck = ?1
+            GROUP BY oracle
+        )
+        SELECT
+            deck.oracle,
+            deck.name,
+            deck.unknown,
+            deck.needed,
+            (
+                SELECT IFNULL(SUM(cards.amount), 0)
+                FROM cards LEFT JOIN scryfall ON scryfall.id = cards.id
+                WHERE (scryfall.oracle_id = deck.oracle OR cards.id = deck.oracle)
+                    AND (?2 IS NULL OR cards.owner = ?2)
+            ),
+            (
+                SELECT MIN(CAST(scryfall.{price} AS REAL))
+                FROM scryfall
+                WHERE scryfall.oracle_id = deck.oracle OR scryfall.id = deck.oracle
+            )
+        FROM deck
+        ORDER BY deck.name"#,
+            price = currency.column()
+        ))?
+        .query_map(rusqlite::params![deck, owner], |row| {
+            Ok(CardStatus {
+                oracle: row.get(0)?,
+                name: row.get(1)?,
+                unknown: row.get(2)?,
+                needed: row.get(3)?,
+                owned: row.get(4)?,
+                committed: Vec::new(),
+                lent: Vec::new(),
+                price: row.get(5)?,
+            })
+        })?
+        .map(|status| -> color_eyre::Result<_> {
+            let mut status = status?;
+            status.committed = committed
+                .query_map([deck, &status.oracle], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?;
+            status.lent = lent
+                .query_map(rusqlite::params![status.oracle, owner], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(status)
+        })
+        .collect::<color_eyre::Result<_>>()?;
+
+    Ok(cards)
+}
+
+impl Args {
+    pub fn deck(self) -> color_eyre::Result<()> {
+        match self {
+            Args::Add(args) => args.add(),
+            Args::List(args) => args.list(),
+            Args::Remove(args) => args.remove(),
+            Args::Check(args) => args.check(),
+            Args::Tokens(args) => args.tokens(),
+            Args::Validate(args) => args.validate(),
+        }
+    }
+}
+
+impl AddArgs {
+    fn add(self) -> color_eyre::Result<()> {
+        let card_list = std::fs::read_to_string(&self.list)?;
+
+        let mut db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let tx = db.transaction()?;
+        {
+            let exists: bool = tx.query_row(
+                "SELECT COUNT(*) > 0 FROM decks WHERE name = ?1",
+                [&self.name],
+                |row| row.get(0),
+            )?;
+            if exists && !self.replace {
+                return Err(eyre!(
+                    "deck {} already exists, use --replace to overwrite it",
+                    self.name
+                ));
+            }
+            tx.execute("DELETE FROM deck_cards WHERE deck = ?1", [&self.name])?;
+            tx.execute(
+                "INSERT OR REPLACE INTO decks (name, format) VALUES (?1, ?2)",
+                rusqlite::params![self.name, self.format],
+            )?;
+
+            let mut resolver =
+                Resolver::with_prefs(&tx, &PrintingFilter::default(), self.ignore_prefs)?;
+            let mut board = Board::Main;
+            for line in card_list.lines() {
+                let entry = match resolver.parse_line(line)? {
+                    Line::Card(entry) => entry,
+                    Line::Section(b) => {
+                        board = b;
+                        continue;
+                    }
+                    Line::Empty => continue,
+                };
+                let id = resolver.resolve(&entry.card)?;
+                tx.execute(
+                    r#"INSERT INTO deck_cards (deck, id, board, amount) VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT (deck, id, board) DO UPDATE SET amount = amount + excluded.amount"#,
+                    rusqlite::params![self.name, id, board.as_str(), entry.count],
+                )?;
+            }
+            resolver.save_prefs()?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+impl ListArgs {
+    fn list(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        for deck in decks(&db)? {
+            println!(
+                "  - {}{}: {} cards, {} in sideboard",
+                deck.name,
+                deck.format.map(|f| format!(" ({})", f)).unwrap_or_default(),
+                deck.main,
+                deck.side
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl DeckArgs {
+    fn remove(self) -> color_eyre::Result<()> {
+        let mut db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+        deck_exists(&db, &self.name)?;
+
+        let tx = db.transaction()?;
+        tx.execute("DELETE FROM deck_cards WHERE deck = ?1", [&self.name])?;
+        tx.execute("DELETE FROM decks WHERE name = ?1", [&self.name])?;
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+impl ValidateArgs {
+    fn validate(self) -> color_eyre::Result<()> {
+        let db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let (printings, saved_format) = match deck_exists(&db, &self.deck) {
+            Ok(()) => {
+                let format: Option<String> = db.query_row(
+                    "SELECT lower(format) FROM decks WHERE name = ?1",
+                    [&self.deck],
+                    |row| row.get(0),
+                )?;
+                (legality::saved_deck(&db, &self.deck)?, format)
+            }
+            Err(_) => (self.list_cards(&db)?, None),
+        };
+        let format = match (self.format, saved_format.as_deref()) {
+            (Some(format), _) => format,
+            (None, Some("edh")) => "commander".into(),
+            (None, Some(format)) if legality::FORMATS.contains(&format) => format.into(),
+            (None, _) => {
+                return Err(eyre!(
+                    "no known format for {}, give one with --format",
+                    self.deck
+                ))
+            }
+        };
+
+        let (cards, unknown) = legality::deck_cards(&db, &printings, &format)?;
+        let violations = match format.as_str() {
+            "commander" => legality::commander(&cards, &unknown),
+            _ => legality::constructed(&cards, &unknown, &format),
+        };
+        if violations.is_empty() {
+            println!("{} follows the {} rules", self.deck, format);
+        } else {
+            println!("{} breaks the {} rules:", self.deck, format);
+            for violation in violations {
+                println!("  - {}", violation);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Printings of a list file, which is not saved as a deck
+    fn list_cards(&self, db: &Connection) -> color_eyre::Result<Vec<(String, Board, usize)>> {
+        let list = std::fs::read_to_string(&self.deck)
+            .wrap_err_with(|| format!("{} is neither a saved deck nor a list", self.deck))?;
+
+        let mut resolver = Resolver::with_prefs(db, &PrintingFilter::default(), self.ignore_prefs)?;
+        let mut board = Board::Main;
+        let mut cards = Vec::new();
+        for line in list.lines() {
+            match resolver.parse_line(line)? {
+                Line::Card(entry) => {
+                    cards.push((resolver.resolve(&entry.card)?, board, entry.count))
+                }
+                Line::Section(b) => board = b,
+                Line::Empty => (),
+            }
+        }
+        resolver.save_prefs()?;
+        Ok(cards)
+    }
+}
+
+impl CheckArgs {
+    fn check(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let cards = check_deck(&db, &self.name, self.owner.as_deref(), self.currency)?;
+
+        let needed: usize = cards.iter().map(|c| c.needed).sum();
+        let missing: usize = cards.iter().map(|c| c.missing()).sum();
+        println!(
+            "Deck {}: {} cards, {} owned, {} missing",
+            self.name,
+            needed,
+            needed - missing,
+            missing
+        );
+
+        let unknown: Vec<_> = cards.iter().filter(|c| c.unknown).collect();
+        if !unknown.is_empty() {
+            println!("Cards missing from the card database:");
+            for card in unknown {
+                println!("  {}", card.name);
+            }
+        }
+
+        if missing > 0 {
+            println!("Missing cards:");
+            let mut cost = 0.;
+            let mut unknown = 0;
+            for card in cards.iter().filter(|c| c.missing() > 0) {
+                match card.price {
+                    Some(price) => {
+                        cost += price * card.missing() as f64;
+                        println!(
+                            "  {} {} ({} each)",
+                            card.missing(),
+                            card.name,
+                            self.currency.format(price)
+                        );
+                    }
+                    None => {
+                        unknown += card.missing();
+                        println!("  {} {} (no price)", card.missing(), card.name);
+                    }
+                }
+            }
+            print!("Missing cards cost {}", self.currency.format(cost));
+            if unknown > 0 {
+                print!(" ({} cards without a price)", unknown);
+            }
+            println!();
+        }
+
+        let shared: Vec<_> = cards
+            .iter()
+            .filter(|c| !c.committed.is_empty() || !c.lent.is_empty())
+            .collect();
+        if !shared.is_empty() {
+            println!("Cards used by other decks or lent:");
+            for card in shared {
+                println!(
+                    "  {}: {} needed, {} owned, {} available{} - {}",
+                    card.name,
+                    card.needed,
+                    card.owned,
+                    card.available(),
+                    if card.available() < card.needed {
+                        " (short)"
+                    } else {
+                        ""
+                    },
+                    card.committed
+                        .iter()
+                        .map(|(deck, amount)| format!("{} in {}", amount, deck))
+                        .chain(
+                            card.lent.iter().map(|(borrower, amount)| format!(
+                                "{} lent to {}",
+                                amount, borrower
+                            )),
+                        )
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TokensArgs {
+    fn tokens(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let decks = match self.name {
+            Some(name) => vec![name],
+            None => decks(&db)?.into_iter().map(|deck| deck.name).collect(),
+        };
+
+        for deck in decks {
+            let tokens = deck_tokens(&db, &deck)?;
+            if tokens.is_empty() {
+                continue;
+            }
+            println!("Deck {}:", deck);
+            for token in tokens {
+                println!(
+                    "  {}{} (made by {}): {}",
+                    token.name,
+                    token
+                        .type_line
+                        .map(|t| format!(" - {}", t))
+                        .unwrap_or_default(),
+                    token.made_by.join(", "),
+                    match token.owned {
+                        0 => "missing".into(),
+                        owned => format!("{} owned", owned),
+                    }
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -4,41 +4,87 @@ use rayon::prelude::*;
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fs::File,
     io::{BufRead, BufReader},
     path::PathBuf,
 };
 use structopt::StructOpt;
 
-use crate::sqlite;
+use crate::{mana, resolve, sqlite};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Card {
     id: String,
+    oracle_id: Option<String>,
     scryfall_uri: String,
     name: String,
     printed_name: Option<String>,
+    lang: String,
+    set: String,
     set_name: String,
+    collector_number: String,
+    rarity: String,
     promo: bool,
     prices: Price,
     variation: bool,
+    layout: String,
+    digital: bool,
+    games: Vec<String>,
+    set_type: String,
+    oversized: bool,
+    #[serde(default)]
+    booster: bool,
     mana_cost: Option<String>,
     keywords: Vec<String>,
     #[serde(default)]
     card_faces: Vec<CardFace>,
+    #[serde(default)]
+    all_parts: Vec<RelatedCard>,
+    /// Missing on double faced cards, whose faces carry their own images
+    image_uris: Option<BTreeMap<String, String>>,
+    /// Missing on multi faced cards, whose faces carry their own
+    type_line: Option<String>,
+    oracle_text: Option<String>,
+    #[serde(default)]
+    color_identity: Vec<String>,
+    /// Mana value of the card, of its front face for double faced cards
+    cmc: Option<f64>,
+    /// Missing on double faced cards, whose faces carry their own
+    colors: Option<Vec<String>>,
+    /// Legality of the card in each format (legal, not_legal, restricted or banned)
+    #[serde(default)]
+    legalities: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RelatedCard {
+    id: String,
+    /// token, meld_part, meld_result or combo_piece
+    component: String,
+    name: String,
+    type_line: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct CardFace {
+    oracle_id: Option<String>,
     name: String,
     printed_name: Option<String>,
     mana_cost: String,
+    image_uris: Option<BTreeMap<String, String>>,
+    type_line: Option<String>,
+    oracle_text: Option<String>,
+    #[serde(default)]
+    colors: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Price {
     eur: Option<String>,
     eur_foil: Option<String>,
+    usd: Option<String>,
+    usd_foil: Option<String>,
 }
 
 #[derive(StructOpt)]
@@ -56,7 +102,12 @@ impl Args {
             INSERT INTO sc.scryfall SELECT * FROM scryfall; 
             INSERT INTO sc.scryfall_faces SELECT * FROM scryfall_faces;
             INSERT INTO sc.scryfall_keywords SELECT * FROM scryfall_keywords;
+            INSERT INTO sc.scryfall_games SELECT * FROM scryfall_games;
+            INSERT INTO sc.scryfall_related SELECT * FROM scryfall_related;
+            INSERT INTO sc.scryfall_images SELECT * FROM scryfall_images;
             INSERT INTO sc.scryfall_mana SELECT * FROM scryfall_mana;
+            INSERT INTO sc.scryfall_mana_symbols SELECT * FROM scryfall_mana_symbols;
+            INSERT OR REPLACE INTO sc.scryfall_legalities SELECT * FROM scryfall_legalities;
             COMMIT; 
             DETACH sc;"#,
             self.database.database.to_string_lossy()
@@ -66,18 +117,48 @@ impl Args {
 
         let table = r#"CREATE TABLE scryfall (
             id TEXT PRIMARY KEY NOT NULL, 
+            oracle_id TEXT,
             name TEXT NOT NULL, 
+            display_name TEXT NOT NULL,
             printed_name TEXT, 
+            lang TEXT NOT NULL,
             eur TEXT, 
             eur_foil TEXT, 
+            usd TEXT,
+            usd_foil TEXT,
             uri TEXT NOT NULL,
+            set_code TEXT NOT NULL,
             set_name TEXT NOT NULL,
+            collector_number TEXT NOT NULL,
+            rarity TEXT NOT NULL,
             promo BOOLEAN NOT NULL,
-            variation BOOLEAN NOT NULL);"#;
+            variation BOOLEAN NOT NULL,
+            layout TEXT NOT NULL,
+            digital BOOLEAN NOT NULL,
+            set_type TEXT NOT NULL,
+            oversized BOOLEAN NOT NULL,
+            booster BOOLEAN NOT NULL,
+            type_line TEXT,
+            oracle_text TEXT,
+            color_identity TEXT NOT NULL,
+            cmc REAL,
+            colors TEXT NOT NULL);"#;
+        // The printings this dump removes must be remembered for `check` to remap them
+        sqlite::create_history_table(sc)?;
+        let history = r#"INSERT OR REPLACE INTO scryfall_history (id, oracle_id, lang, set_code, collector_number)
+            SELECT id, oracle_id, lang, set_code, collector_number FROM scryfall"#;
+        // Dumps made by older versions lack the identity of the printings, there is nothing to keep
+        let mut identified = sqlite::has_table(sc, "scryfall")?;
+        for column in &["oracle_id", "lang", "set_code", "collector_number"] {
+            identified = identified && sqlite::has_column(sc, "scryfall", column)?;
+        }
+        if identified {
+            sc.execute(history, [])?;
+        }
         sc.execute("DROP TABLE IF EXISTS scryfall;", [])?;
-        sc.execute(&table, [])?;
+        sc.execute(table, [])?;
 
-        let face_table = "CREATE TABLE scryfall_faces (id TEXT NOT NULL, name TEXT NOT NULL, PRIMARY KEY (id, name));";
+        let face_table = "CREATE TABLE scryfall_faces (id TEXT NOT NULL, face INTEGER NOT NULL, name TEXT NOT NULL, display_name TEXT NOT NULL, PRIMARY KEY (id, face));";
         sc.execute("DROP TABLE IF EXISTS scryfall_faces;", [])?;
         sc.execute(face_table, [])?;
 
@@ -85,10 +166,33 @@ impl Args {
         sc.execute("DROP TABLE IF EXISTS scryfall_keywords;", [])?;
         sc.execute(gameplay_table, [])?;
 
+        let games_table = "CREATE TABLE scryfall_games (id TEXT NOT NULL, game TEXT NOT NULL, PRIMARY KEY (id, game));";
+        sc.execute("DROP TABLE IF EXISTS scryfall_games;", [])?;
+        sc.execute(games_table, [])?;
+
+        let related_table = "CREATE TABLE scryfall_related (id TEXT NOT NULL, related_id TEXT NOT NULL, component TEXT NOT NULL, name TEXT NOT NULL, type_line TEXT, PRIMARY KEY (id, related_id));";
+        sc.execute("DROP TABLE IF EXISTS scryfall_related;", [])?;
+        sc.execute(related_table, [])?;
+
+        let images_table = "CREATE TABLE scryfall_images (id TEXT NOT NULL, face INTEGER NOT NULL, kind TEXT NOT NULL, uri TEXT NOT NULL, PRIMARY KEY (id, face, kind));";
+        sc.execute("DROP TABLE IF EXISTS scryfall_images;", [])?;
+        sc.execute(images_table, [])?;
+
         let mana_table = "CREATE TABLE scryfall_mana (id TEXT NOT NULL, face INTEGER NOT NULL,cost TEXT NOT NULL, PRIMARY KEY (id, face))";
         sc.execute("DROP TABLE IF EXISTS scryfall_mana;", [])?;
         sc.execute(mana_table, [])?;
 
+        // Generic costs are stored as their number of `{1}`
+        let symbols_table = "CREATE TABLE scryfall_mana_symbols (id TEXT NOT NULL, face INTEGER NOT NULL, symbol TEXT NOT NULL, kind TEXT NOT NULL, amount INTEGER NOT NULL, PRIMARY KEY (id, face, symbol))";
+        sc.execute("DROP TABLE IF EXISTS scryfall_mana_symbols;", [])?;
+        sc.execute(symbols_table, [])?;
+
+        // Legalities are the same for every printing of a card, the formats where it is not
+        // legal are left out
+        let legalities_table = "CREATE TABLE scryfall_legalities (oracle TEXT NOT NULL, format TEXT NOT NULL, legality TEXT NOT NULL, PRIMARY KEY (oracle, format))";
+        sc.execute("DROP TABLE IF EXISTS scryfall_legalities;", [])?;
+        sc.execute(legalities_table, [])?;
+
         println!("Creating scryfall databases:");
         let mut input = BufReader::new(File::open(&self.scryfall_jsonl)?).lines();
         let count = input
@@ -102,42 +206,91 @@ impl Args {
         .try_fold(
             || {
                 let con = Connection::open_in_memory().expect("could not open in memory");
-                con.execute(&table, []).expect("could not create schema");
-                con.execute(&face_table, []).expect("Could not create face schema");
-                con.execute(&gameplay_table, []).expect("Could not create keyword schema");
-                con.execute(&mana_table, []).expect("Could not create mana schema");
+                con.execute(table, []).expect("could not create schema");
+                con.execute(face_table, []).expect("Could not create face schema");
+                con.execute(gameplay_table, []).expect("Could not create keyword schema");
+                con.execute(games_table, []).expect("Could not create games schema");
+                con.execute(related_table, []).expect("Could not create related schema");
+                con.execute(images_table, []).expect("Could not create images schema");
+                con.execute(mana_table, []).expect("Could not create mana schema");
+                con.execute(symbols_table, []).expect("Could not create mana symbols schema");
+                con.execute(legalities_table, []).expect("Could not create legalities schema");
                 con
             },
             |con, card| -> color_eyre::Result<_> {
                 let card = card?;
-                let name = card.name.to_lowercase();
-                let printed_name =
-                    card.printed_name
-                        .as_deref()
-                        .map(deunicode::deunicode)
-                        .map(|mut s| {
-                            s.make_ascii_lowercase();
-                            s
-                        });
+                let name = resolve::fold_name(&card.name);
+                let printed_name = card.printed_name.as_deref().map(resolve::fold_name);
+
+                // Reversible cards only carry their oracle id on the faces
+                let oracle_id = card.oracle_id.as_ref().or_else(|| card.card_faces.iter().find_map(|f| f.oracle_id.as_ref()));
+
+                let faces = |field: fn(&CardFace) -> Option<&String>, separator| {
+                    let faces: Vec<_> = card.card_faces.iter().filter_map(field).map(String::as_str).collect();
+                    Some(faces.join(separator)).filter(|joined| !joined.is_empty())
+                };
+                let type_line = card.type_line.clone().or_else(|| faces(|f| f.type_line.as_ref(), " // "));
+                let oracle_text = card.oracle_text.clone().or_else(|| faces(|f| f.oracle_text.as_ref(), "\n//\n"));
+                let color_identity = card.color_identity.concat();
+                let colors: String = match &card.colors {
+                    Some(colors) => colors.concat(),
+                    None => "WUBRG".chars().filter(|c| card.card_faces.iter().any(|f| f.colors.iter().any(|color| color.starts_with(*c)))).collect(),
+                };
 
                 con.execute(
-                    r#"INSERT OR REPLACE INTO scryfall (id, name, printed_name, eur, eur_foil, uri, set_name, promo, variation) 
-                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"#,
-                    rusqlite::params![card.id, name, printed_name, card.prices.eur, card.prices.eur_foil, card.scryfall_uri, card.set_name, card.promo, card.variation],
+                    r#"INSERT OR REPLACE INTO scryfall (id, oracle_id, name, display_name, printed_name, lang, eur, eur_foil, usd, usd_foil, uri, set_code, set_name, collector_number, rarity, promo, variation, layout, digital, set_type, oversized, booster, type_line, oracle_text, color_identity, cmc, colors) 
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27)"#,
+                    rusqlite::params![card.id, oracle_id, name, card.name, printed_name, card.lang, card.prices.eur, card.prices.eur_foil, card.prices.usd, card.prices.usd_foil, card.scryfall_uri, card.set, card.set_name, card.collector_number, card.rarity, card.promo, card.variation, card.layout, card.digital, card.set_type, card.oversized, card.booster, type_line, oracle_text, color_identity, card.cmc, colors],
                 )?;
 
-                if let Some(cost) = card.mana_cost {
-                    con.execute("INSERT OR REPLACE INTO scryfall_mana (id,face,cost) VALUES (?1,0,?2)", [&card.id, &cost])?;
+                for (format, legality) in &card.legalities {
+                    if legality != "not_legal" {
+                        con.execute("INSERT OR REPLACE INTO scryfall_legalities (oracle, format, legality) VALUES (?1, ?2, ?3)", rusqlite::params![oracle_id.unwrap_or(&card.id), format, legality])?;
+                    }
+                }
+
+                let card_id = &card.id;
+                let insert_cost = |face: usize, cost: &str| -> rusqlite::Result<()> {
+                    con.execute("INSERT OR REPLACE INTO scryfall_mana (id,face,cost) VALUES (?1,?2,?3)", rusqlite::params![card_id, face, cost])?;
+                    if let Ok(mana::Cost(symbols)) = cost.parse() {
+                        for (symbol, amount) in symbols {
+                            con.execute("INSERT OR REPLACE INTO scryfall_mana_symbols (id, face, symbol, kind, amount) VALUES (?1, ?2, ?3, ?4, ?5)", rusqlite::params![card_id, face, symbol.to_string(), symbol.kind(), amount])?;
+                        }
+                    }
+                    Ok(())
+                };
+
+                if let Some(cost) = &card.mana_cost {
+                    insert_cost(0, cost)?;
                 }
 
                 for keyword in  card.keywords {
                     con.execute("INSERT OR REPLACE INTO scryfall_keywords (id, keyword) VALUES (?1, ?2)", [&card.id, &keyword])?;
                 }
 
+                for game in card.games {
+                    con.execute("INSERT OR REPLACE INTO scryfall_games (id, game) VALUES (?1, ?2)", [&card.id, &game])?;
+                }
+
+                // Cards are listed among their own parts
+                for part in &card.all_parts {
+                    if part.id == card.id {
+                        continue;
+                    }
+                    con.execute("INSERT OR REPLACE INTO scryfall_related (id, related_id, component, name, type_line) VALUES (?1, ?2, ?3, ?4, ?5)", rusqlite::params![&card.id, &part.id, &part.component, &part.name, &part.type_line])?;
+                }
+
+                let faces = card.card_faces.iter().map(|f| &f.image_uris);
+                for (face, uris) in std::iter::once(&card.image_uris).chain(faces).enumerate() {
+                    for (kind, uri) in uris.iter().flatten() {
+                        con.execute("INSERT OR REPLACE INTO scryfall_images (id, face, kind, uri) VALUES (?1, ?2, ?3, ?4)", rusqlite::params![&card.id, face, kind, uri])?;
+                    }
+                }
+
                 for (id, face) in card.card_faces.iter().enumerate() {
-                    let face_name = deunicode::deunicode(face.printed_name.as_ref().unwrap_or(&face.name)).to_ascii_lowercase();
-                    con.execute("INSERT OR REPLACE INTO scryfall_faces (id, name) VALUES (?1, ?2)", [&card.id, &face_name])?;
-                    con.execute("INSERT OR REPLACE INTO scryfall_mana (id,face,cost) VALUES (?1,?2,?3)", rusqlite::params![&card.id, id + 1, &face.mana_cost])?;
+                    let face_name = resolve::fold_name(face.printed_name.as_ref().unwrap_or(&face.name));
+                    con.execute("INSERT OR REPLACE INTO scryfall_faces (id, face, name, display_name) VALUES (?1, ?2, ?3, ?4)", rusqlite::params![&card.id, id + 1, &face_name, face.printed_name.as_ref().unwrap_or(&face.name)])?;
+                    insert_cost(id + 1, &face.mana_cost)?;
                 }
 
                 Ok(con)
@@ -150,6 +303,14 @@ impl Args {
             .progress()
             .try_for_each(|connection| connection.execute_batch(&attach_str))?;
 
+        sc.execute_batch(
+            r#"
+            CREATE INDEX scryfall_oracle_id ON scryfall (oracle_id);
+            CREATE INDEX scryfall_set ON scryfall (set_code, collector_number);
+            CREATE INDEX scryfall_faces_name ON scryfall_faces (name);
+            "#,
+        )?;
+
         println!("Creating vocabulary:");
         sc.execute_batch(r#"
                          DROP TABLE IF EXISTS card_names;
@@ -161,6 +322,48 @@ impl Args {
                          INSERT INTO face_names(word) SELECT DISTINCT name FROM scryfall_faces;
                          "#)?;
 
+        println!("Updating card history:");
+        sc.execute(history, [])?;
+
+        sqlite::create_collection_tables(sc)?;
+        let orphans: usize = sc.query_row(
+            "SELECT COUNT(*) FROM cards WHERE id NOT IN (SELECT id FROM scryfall)",
+            [],
+            |row| row.get(0),
+        )?;
+        if orphans > 0 {
+            println!(
+                "{} collection entries reference unknown cards, run `mtg_db check` to fix them",
+                orphans
+            );
+        }
+
+        // Costs that could not be read have no symbols, their cards never match cost searches
+        let unreadable = r#"
+            FROM scryfall_mana JOIN scryfall ON scryfall.id = scryfall_mana.id
+            WHERE scryfall_mana.cost != '' AND NOT EXISTS (
+                SELECT 1 FROM scryfall_mana_symbols AS symbols
+                WHERE symbols.id = scryfall_mana.id AND symbols.face = scryfall_mana.face
+            )"#;
+        let unreadable_costs: usize =
+            sc.query_row(&format!("SELECT COUNT(*) {}", unreadable), [], |row| {
+                row.get(0)
+            })?;
+        if unreadable_costs > 0 {
+            let examples = sc
+                .prepare(&format!(
+                    "SELECT DISTINCT scryfall.display_name || ' ' || scryfall_mana.cost {} LIMIT 5",
+                    unreadable
+                ))?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            println!(
+                "{} mana costs could not be read, their cards will not match cost searches: {}",
+                unreadable_costs,
+                examples.join(", ")
+            );
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,231 @@
+use color_eyre::eyre::eyre;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use structopt::StructOpt;
+
+use crate::{filter::CardFilter, sqlite};
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Csv,
+    Json,
+    /// Arena lists have no room for tags, they are dropped like in plain lists
+    Arena,
+    Moxfield,
+    Deckbox,
+    Plain,
+}
+
+impl Format {
+    pub const VARIANTS: &'static [&'static str] =
+        &["csv", "json", "arena", "moxfield", "deckbox", "plain"];
+}
+
+impl FromStr for Format {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "arena" => Ok(Format::Arena),
+            "moxfield" => Ok(Format::Moxfield),
+            "deckbox" => Ok(Format::Deckbox),
+            "plain" => Ok(Format::Plain),
+            _ => Err(eyre!("unknown export format {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExportedCard {
+    pub name: String,
+    pub set_code: String,
+    pub set_name: String,
+    pub collector_number: String,
+    pub foil: bool,
+    pub amount: usize,
+    pub id: String,
+    /// Tags of the copies, with the number of tagged copies when not all of them are
+    pub tags: String,
+}
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    #[structopt(flatten)]
+    filter: CardFilter,
+    #[structopt(long, short, default_value = "csv", possible_values = Format::VARIANTS)]
+    format: Format,
+    /// File to write the export to, defaults to the standard output
+    #[structopt(long, short)]
+    output: Option<PathBuf>,
+}
+
+/// Fetches the owned cards matching `filter`, with the information needed to export them
+pub fn owned_cards(db: &Connection, filter: &CardFilter) -> color_eyre::Result<Vec<ExportedCard>> {
+    let (owned, mut params) = filter.owned_cards();
+    // The tags are selected before the owned cards, so their owner comes first
+    params.insert(0, Box::new(filter.owner.clone()));
+    params.insert(0, Box::new(filter.owner.clone()));
+    let query = format!(
+        r#"
+        SELECT
+            scryfall.display_name,
+            scryfall.set_code,
+            scryfall.set_name,
+            scryfall.collector_number,
+            filtered.foil,
+            filtered.amount,
+            scryfall.id,
+            (
+                SELECT group_concat(IIF(tagged < filtered.amount, tag || ' (' || tagged || ')', tag), ', ')
+                FROM (
+                    SELECT tag, SUM(amount) AS tagged FROM card_tags
+                    WHERE id = filtered.id AND foil = filtered.foil AND (? IS NULL OR owner = ?)
+                    GROUP BY tag
+                )
+            )
+        FROM ({}) AS filtered JOIN scryfall ON scryfall.id = filtered.id
+        ORDER BY scryfall.name, scryfall.set_code, scryfall.collector_number, filtered.foil"#,
+        owned
+    );
+
+    let cards = db
+        .prepare(&query)?
+        .query_map(rusqlite::params_from_iter(params.iter()), |row| {
+            Ok(ExportedCard {
+                name: row.get(0)?,
+                set_code: row.get(1)?,
+                set_name: row.get(2)?,
+                collector_number: row.get(3)?,
+                foil: row.get(4)?,
+                amount: row.get(5)?,
+                id: row.get(6)?,
+                tags: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(cards)
+}
+
+pub fn write_cards<W: Write>(
+    format: Format,
+    cards: &[ExportedCard],
+    mut out: W,
+) -> color_eyre::Result<()> {
+    let foil = |card: &ExportedCard| if card.foil { "foil" } else { "" };
+
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for card in cards {
+                writer.serialize(card)?;
+            }
+            writer.flush()?;
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, cards)?;
+            writeln!(out)?;
+            out.flush()?;
+        }
+        Format::Arena => {
+            for card in cards {
+                writeln!(
+                    out,
+                    "{} {} ({}) {}",
+                    card.amount,
+                    card.name,
+                    card.set_code.to_uppercase(),
+                    card.collector_number
+                )?;
+            }
+            out.flush()?;
+        }
+        Format::Moxfield => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record([
+                "Count",
+                "Name",
+                "Edition",
+                "Foil",
+                "Collector Number",
+                "Tags",
+            ])?;
+            for card in cards {
+                writer.write_record([
+                    &card.amount.to_string(),
+                    &card.name,
+                    &card.set_code,
+                    foil(card),
+                    &card.collector_number,
+                    &card.tags,
+                ])?;
+            }
+            writer.flush()?;
+        }
+        Format::Deckbox => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(["Count", "Name", "Edition", "Card Number", "Foil", "Tags"])?;
+            for card in cards {
+                writer.write_record([
+                    &card.amount.to_string(),
+                    &card.name,
+                    &card.set_name,
+                    &card.collector_number,
+                    foil(card),
+                    &card.tags,
+                ])?;
+            }
+            writer.flush()?;
+        }
+        Format::Plain => {
+            for card in cards {
+                writeln!(out, "{} {}", card.amount, card.name)?;
+            }
+            out.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the cards to `output`, or to the standard output if there is none
+pub fn write_output(
+    format: Format,
+    cards: &[ExportedCard],
+    output: Option<&Path>,
+) -> color_eyre::Result<()> {
+    match output {
+        None => write_cards(format, cards, std::io::stdout().lock()),
+        Some(p) => write_cards(
+            format,
+            cards,
+            BufWriter::new(
+                OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .open(p)?,
+            ),
+        ),
+    }
+}
+
+impl Args {
+    pub fn export(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let cards = owned_cards(&db, &self.filter)?;
+
+        write_output(self.format, &cards, self.output.as_deref())
+    }
+}
@@ -0,0 +1,155 @@
+use rusqlite::ToSql;
+use structopt::StructOpt;
+
+use crate::resolve;
+
+// Restricts the owned cards a command works on
+#[derive(StructOpt, Default)]
+pub struct CardFilter {
+    /// Only cards added through the list with this name or uid
+    #[structopt(long)]
+    pub list: Option<String>,
+    /// Only cards added through lists stored at this location
+    #[structopt(long)]
+    pub location: Option<String>,
+    /// Only cards whose name contains this text
+    #[structopt(long, short = "q")]
+    pub query: Option<String>,
+    /// Only cards from the set with this code
+    #[structopt(long)]
+    pub set: Option<String>,
+    /// Only the copies with this tag
+    #[structopt(long)]
+    pub tag: Option<String>,
+    /// Only the cards of this owner
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    pub owner: Option<String>,
+}
+
+impl CardFilter {
+    /// Builds a query returning the `id`, `foil` and `amount` of the owned cards matching the
+    /// filter, along with its parameters
+    pub fn owned_cards(&self) -> (String, Vec<Box<dyn ToSql>>) {
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        let cards = match &self.owner {
+            Some(owner) => {
+                params.push(Box::new(owner.clone()));
+                "SELECT id, foil, owner, amount FROM cards WHERE owner = ?"
+            }
+            None => "SELECT id, foil, owner, amount FROM cards",
+        };
+        let source = if self.list.is_some() || self.location.is_some() {
+            let mut conditions = Vec::new();
+            if let Some(list) = &self.list {
+                conditions.push("(lists.name = ? OR hex(lists.hash) = upper(?))");
+                params.push(Box::new(list.clone()));
+                params.push(Box::new(list.clone()));
+            }
+            if let Some(location) = &self.location {
+                conditions.push("lists.location = ?");
+                params.push(Box::new(location.clone()));
+            }
+            // Cards may have been removed since the list was added
+            format!(
+                r#"
+                SELECT
+                    cards.id,
+                    cards.foil,
+                    cards.owner,
+                    MIN(SUM(list_cards.amount), cards.amount) AS amount
+                FROM
+                    list_cards
+                    JOIN lists ON lists.hash = list_cards.hash
+                    JOIN ({}) AS cards ON cards.id = list_cards.id
+                        AND cards.foil = list_cards.foil AND cards.owner = lists.owner
+                WHERE {}
+                GROUP BY cards.id, cards.foil, cards.owner"#,
+                cards,
+                conditions.join(" AND ")
+            )
+        } else {
+            cards.to_string()
+        };
+
+        let (amount, tagged) = match &self.tag {
+            Some(tag) => {
+                params.push(Box::new(tag.clone()));
+                (
+                    "MIN(owned.amount, card_tags.amount)",
+                    r#"JOIN card_tags ON card_tags.id = owned.id AND card_tags.foil = owned.foil
+                AND card_tags.owner = owned.owner AND card_tags.tag = ?"#,
+                )
+            }
+            None => ("owned.amount", ""),
+        };
+
+        let mut conditions = vec!["owned.amount > 0"];
+        if let Some(query) = &self.query {
+            conditions.push(
+                "(scryfall.name LIKE '%' || ? || '%' OR scryfall.printed_name LIKE '%' || ? || '%')",
+            );
+            params.push(Box::new(resolve::fold_name(query)));
+            params.push(Box::new(resolve::fold_name(query)));
+        }
+        if let Some(set) = &self.set {
+            conditions.push("scryfall.set_code = lower(?)");
+            params.push(Box::new(set.clone()));
+        }
+
+        // Tags belong to an owner, so the copies of each owner are matched before being summed
+        let query = format!(
+            r#"
+            SELECT owned.id, owned.foil, SUM({}) AS amount
+            FROM ({}) AS owned JOIN scryfall ON scryfall.id = owned.id
+            {}
+            WHERE {}
+            GROUP BY owned.id, owned.foil"#,
+            amount,
+            source,
+            tagged,
+            conditions.join(" AND ")
+        );
+
+        (query, params)
+    }
+}
+
+// Restricts the printings offered when looking cards up. By default only the paper printings
+// that are neither tokens nor art series cards are kept.
+#[derive(StructOpt, Default)]
+pub struct PrintingFilter {
+    /// Also offer the printings that only exist on Arena or MTGO
+    #[structopt(long)]
+    pub digital: bool,
+    /// Also offer tokens and emblems
+    #[structopt(long)]
+    pub tokens: bool,
+    /// Also offer the cards of art series
+    #[structopt(long)]
+    pub art_series: bool,
+}
+
+impl PrintingFilter {
+    /// Condition on the `scryfall` table selecting the printings to offer
+    pub fn condition(&self) -> String {
+        let mut conditions = vec!["1"];
+        if !self.digital {
+            conditions.push(
+                r#"NOT scryfall.digital AND EXISTS (
+                    SELECT 1 FROM scryfall_games
+                    WHERE scryfall_games.id = scryfall.id AND scryfall_games.game = 'paper'
+                )"#,
+            );
+        }
+        if !self.tokens {
+            conditions.push(
+                "scryfall.layout NOT IN ('token', 'double_faced_token', 'emblem') AND scryfall.set_type != 'token'",
+            );
+        }
+        if !self.art_series {
+            conditions.push("scryfall.layout != 'art_series'");
+        }
+        conditions.join(" AND ")
+    }
+}
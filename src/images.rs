@@ -0,0 +1,267 @@
+use color_eyre::eyre::{eyre, WrapErr};
+use indicatif::{ParallelProgressIterator, ProgressBar};
+use rayon::prelude::*;
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+
+use crate::sqlite;
+
+pub const KINDS: &[&str] = &["small", "normal", "large", "png", "art_crop", "border_crop"];
+
+/// Images are written once per download batch, so an interrupted fetch resumes from there
+const BATCH: usize = 64;
+
+#[derive(StructOpt)]
+pub enum Args {
+    /// Download the images of the owned cards, or of every card, into the cache
+    Fetch(FetchArgs),
+    /// List the owned cards that have no cached image
+    Missing(MissingArgs),
+}
+
+#[derive(StructOpt)]
+pub struct Cache {
+    /// Directory holding the downloaded images, named by the hash of their content
+    #[structopt(long = "cache", env = "MTG_DB_IMAGES", default_value = "images")]
+    pub dir: PathBuf,
+    #[structopt(long, default_value = "normal", possible_values = KINDS)]
+    pub kind: String,
+}
+
+#[derive(StructOpt)]
+pub struct FetchArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    #[structopt(flatten)]
+    cache: Cache,
+    /// Download from this server instead of scryfall, keeping the path of the images
+    #[structopt(long)]
+    base_url: Option<String>,
+    /// Number of parallel downloads
+    #[structopt(long, short, default_value = "8")]
+    jobs: usize,
+    /// Fetch the images of every card, not only the owned ones and the ones in decks
+    #[structopt(long)]
+    all: bool,
+}
+
+#[derive(StructOpt)]
+pub struct MissingArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    #[structopt(flatten)]
+    cache: Cache,
+}
+
+impl Args {
+    pub fn images(self) -> color_eyre::Result<()> {
+        match self {
+            Args::Fetch(args) => args.fetch(),
+            Args::Missing(args) => args.missing(),
+        }
+    }
+}
+
+struct Image {
+    id: String,
+    face: u32,
+    uri: String,
+}
+
+impl Cache {
+    fn path(&self, hash: &str, uri: &str) -> PathBuf {
+        let extension = Path::new(uri.split('?').next().unwrap_or(uri))
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("jpg");
+        self.dir
+            .join(&hash[..2])
+            .join(format!("{}.{}", hash, extension))
+    }
+
+    /// Path of the cached image of a face of a card, 0 being the whole card
+    pub fn lookup(
+        &self,
+        db: &Connection,
+        id: &str,
+        face: u32,
+    ) -> rusqlite::Result<Option<PathBuf>> {
+        let mut statement = db.prepare(
+            "SELECT uri, hash FROM cached_images WHERE id = ?1 AND face = ?2 AND kind = ?3",
+        )?;
+        let mut rows = statement.query(rusqlite::params![id, face, &self.kind])?;
+        Ok(match rows.next()? {
+            Some(row) => {
+                let uri: String = row.get(0)?;
+                let hash: String = row.get(1)?;
+                Some(self.path(&hash, &uri)).filter(|p| p.exists())
+            }
+            None => None,
+        })
+    }
+
+    fn store(&self, data: &[u8], uri: &str) -> color_eyre::Result<String> {
+        let hash = hex::encode(Sha256::digest(data));
+        let path = self.path(&hash, uri);
+        if !path.exists() {
+            let dir = path.parent().unwrap();
+            fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("could not create {}", dir.display()))?;
+            let partial = path.with_extension("part");
+            fs::write(&partial, data)?;
+            fs::rename(&partial, &path)?;
+        }
+        Ok(hash)
+    }
+}
+
+/// Replaces the scheme and host of `uri` by `base`
+fn mirror(uri: &str, base: &str) -> String {
+    let path = match uri.find("://") {
+        Some(scheme) => uri[scheme + 3..]
+            .find('/')
+            .map(|p| &uri[scheme + 3 + p..])
+            .unwrap_or(""),
+        None => uri,
+    };
+    format!("{}{}", base.trim_end_matches('/'), path)
+}
+
+fn download(url: &str) -> color_eyre::Result<Vec<u8>> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| eyre!("could not download image: {}", e))?;
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut data)
+        .wrap_err_with(|| format!("could not read {}", url))?;
+    Ok(data)
+}
+
+impl FetchArgs {
+    fn fetch(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let mut statement = db.prepare(
+            r#"
+            SELECT scryfall_images.id, scryfall_images.face, scryfall_images.uri, cached_images.hash
+            FROM scryfall_images
+            LEFT JOIN cached_images ON cached_images.id = scryfall_images.id
+                AND cached_images.face = scryfall_images.face
+                AND cached_images.kind = scryfall_images.kind
+                AND cached_images.uri = scryfall_images.uri
+            WHERE scryfall_images.kind = ?1
+                AND (?2 OR scryfall_images.id IN (SELECT id FROM cards UNION SELECT id FROM deck_cards))"#,
+        )?;
+        let mut rows = statement.query(rusqlite::params![&self.cache.kind, self.all])?;
+        let mut images = Vec::new();
+        while let Some(row) = rows.next()? {
+            let image = Image {
+                id: row.get(0)?,
+                face: row.get(1)?,
+                uri: row.get(2)?,
+            };
+            let hash: Option<String> = row.get(3)?;
+            match hash {
+                Some(hash) if self.cache.path(&hash, &image.uri).exists() => (),
+                _ => images.push(image),
+            }
+        }
+
+        println!("Downloading {} images:", images.len());
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs.max(1))
+            .build()?;
+        let progress = ProgressBar::new(images.len() as u64);
+        let mut failures = Vec::new();
+        for batch in images.chunks(BATCH) {
+            let fetched: Vec<_> = pool.install(|| {
+                batch
+                    .par_iter()
+                    .progress_with(progress.clone())
+                    .map(|image| {
+                        let url = match &self.base_url {
+                            Some(base) => mirror(&image.uri, base),
+                            None => image.uri.clone(),
+                        };
+                        (
+                            image,
+                            download(&url).and_then(|data| self.cache.store(&data, &image.uri)),
+                        )
+                    })
+                    .collect()
+            });
+
+            let tx = db.unchecked_transaction()?;
+            for (image, hash) in fetched {
+                match hash {
+                    Ok(hash) => {
+                        tx.execute(
+                            "INSERT OR REPLACE INTO cached_images (id, face, kind, uri, hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+                            rusqlite::params![&image.id, image.face, &self.cache.kind, &image.uri, &hash],
+                        )?;
+                    }
+                    Err(e) => failures.push(e),
+                }
+            }
+            tx.commit()?;
+        }
+        progress.finish();
+
+        if !failures.is_empty() {
+            println!("Could not download {} images:", failures.len());
+            for failure in &failures {
+                println!("  {}", failure);
+            }
+        }
+
+        report_missing(&db, &self.cache)
+    }
+}
+
+impl MissingArgs {
+    fn missing(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+        report_missing(&db, &self.cache)
+    }
+}
+
+fn report_missing(db: &Connection, cache: &Cache) -> color_eyre::Result<()> {
+    let mut statement = db.prepare(
+        r#"
+        SELECT DISTINCT cards.id, scryfall.display_name, upper(scryfall.set_code), scryfall.collector_number
+        FROM cards JOIN scryfall ON scryfall.id = cards.id
+        WHERE cards.amount > 0
+        ORDER BY scryfall.display_name, scryfall.set_code"#,
+    )?;
+    let mut rows = statement.query([])?;
+    let mut missing = Vec::new();
+    while let Some(row) = rows.next()? {
+        let id: String = row.get(0)?;
+        if cache.lookup(db, &id, 0)?.is_none() && cache.lookup(db, &id, 1)?.is_none() {
+            let name: String = row.get(1)?;
+            let set: String = row.get(2)?;
+            let number: String = row.get(3)?;
+            missing.push(format!("{} ({} {})", name, set, number));
+        }
+    }
+
+    if missing.is_empty() {
+        println!("Every owned card has a cached image");
+    } else {
+        println!("{} owned cards have no cached image:", missing.len());
+        for card in missing {
+            println!("  {}", card);
+        }
+    }
+    Ok(())
+}
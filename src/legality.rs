@@ -0,0 +1,362 @@
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::resolve::Board;
+
+/// Formats decks can be validated for
+pub const FORMATS: &[&str] = &[
+    "commander",
+    "standard",
+    "pioneer",
+    "modern",
+    "legacy",
+    "vintage",
+    "pauper",
+];
+
+/// A card of a deck, all printings of it in the same board taken together
+#[derive(Debug)]
+pub struct DeckCard {
+    pub oracle: String,
+    pub name: String,
+    pub board: Board,
+    pub count: usize,
+    pub type_line: String,
+    pub oracle_text: String,
+    /// Colors of the identity, as `WUBRG` letters
+    pub color_identity: String,
+    /// Legality in the format the deck is checked for, `None` when the card is not legal there
+    pub legality: Option<String>,
+    /// Whether the card was printed at common in paper or on MTGO
+    pub common: bool,
+}
+
+impl DeckCard {
+    /// Type line of the front face
+    fn front_type(&self) -> &str {
+        self.type_line.split(" // ").next().unwrap_or_default()
+    }
+
+    /// Abilities of the card, without their reminder text
+    fn abilities(&self) -> impl Iterator<Item = &str> {
+        self.oracle_text
+            .lines()
+            .map(|line| line.split(" (").next().unwrap_or_default().trim())
+    }
+
+    /// Whether the card has a keyword ability, alone on its line or in a list of keywords
+    fn has_keyword(&self, keyword: &str) -> bool {
+        self.abilities().any(|ability| {
+            ability
+                .split(", ")
+                .any(|word| word.eq_ignore_ascii_case(keyword))
+        })
+    }
+
+    /// Name of the card this one has "Partner with"
+    fn partner_with(&self) -> Option<&str> {
+        self.abilities()
+            .find_map(|ability| ability.strip_prefix("Partner with "))
+    }
+
+    /// Number of copies a deck can hold, `None` when there is no limit
+    pub fn copy_limit(&self, default: usize) -> Option<usize> {
+        if self.front_type().starts_with("Basic ") {
+            return None;
+        }
+        for ability in self.abilities() {
+            if ability.starts_with("A deck can have any number of cards named") {
+                return None;
+            }
+            if let Some(rest) = ability.strip_prefix("A deck can have up to ") {
+                let number = rest.split(' ').next().unwrap_or_default();
+                let limit = [
+                    "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+                ]
+                .iter()
+                .position(|&n| n == number)
+                .map(|n| n + 1)
+                .or_else(|| number.parse().ok());
+                if let Some(limit) = limit {
+                    return Some(limit);
+                }
+            }
+        }
+        Some(default)
+    }
+}
+
+/// Id of a printing of a deck, with its board and number of copies
+pub type Printing = (String, Board, usize);
+
+/// Printings of a saved deck
+pub fn saved_deck(db: &Connection, deck: &str) -> color_eyre::Result<Vec<Printing>> {
+    let mut statement = db.prepare("SELECT id, board, amount FROM deck_cards WHERE deck = ?1")?;
+    let mut rows = statement.query([deck])?;
+    let mut cards = Vec::new();
+    while let Some(row) = rows.next()? {
+        let board: String = row.get(1)?;
+        cards.push((row.get(0)?, board.parse()?, row.get(2)?));
+    }
+    Ok(cards)
+}
+
+/// Gathers the rules text of the printings of a deck, along with their legality in `format`.
+/// The printings missing from the card database are returned apart.
+pub fn deck_cards(
+    db: &Connection,
+    printings: &[Printing],
+    format: &str,
+) -> rusqlite::Result<(Vec<DeckCard>, Vec<Printing>)> {
+    let mut statement = db.prepare(
+        r#"
+        SELECT
+            IFNULL(scryfall.oracle_id, scryfall.id),
+            scryfall.display_name,
+            IFNULL(scryfall.type_line, ''),
+            IFNULL(scryfall.oracle_text, ''),
+            scryfall.color_identity,
+            (
+                SELECT legality FROM scryfall_legalities
+                WHERE oracle = IFNULL(scryfall.oracle_id, scryfall.id) AND format = ?2
+            ),
+            EXISTS (
+                SELECT 1 FROM scryfall AS printing
+                WHERE (printing.oracle_id = scryfall.oracle_id OR printing.id = scryfall.id)
+                    AND printing.rarity = 'common'
+                    AND printing.id IN (
+                        SELECT id FROM scryfall_games WHERE game IN ('paper', 'mtgo')
+                    )
+            )
+        FROM scryfall
+        WHERE scryfall.id = ?1"#,
+    )?;
+
+    let mut cards: BTreeMap<(String, &str), DeckCard> = BTreeMap::new();
+    let mut unknown = Vec::new();
+    for (id, board, count) in printings {
+        let card = statement
+            .query_row(rusqlite::params![id, format], |row| {
+                Ok(DeckCard {
+                    oracle: row.get(0)?,
+                    name: row.get(1)?,
+                    board: *board,
+                    count: *count,
+                    type_line: row.get(2)?,
+                    oracle_text: row.get(3)?,
+                    color_identity: row.get(4)?,
+                    legality: row.get(5)?,
+                    common: row.get(6)?,
+                })
+            })
+            .optional()?;
+        match card {
+            Some(card) => {
+                cards
+                    .entry((card.oracle.clone(), board.as_str()))
+                    .and_modify(|c| c.count += card.count)
+                    .or_insert(card);
+            }
+            None => unknown.push((id.clone(), *board, *count)),
+        }
+    }
+
+    let mut cards: Vec<_> = cards.into_values().collect();
+    cards.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((cards, unknown))
+}
+
+/// Reports the printings missing from the card database, whose rules cannot be checked
+fn unknown_cards(unknown: &[Printing]) -> Vec<String> {
+    // A printing may be in several boards
+    let ids: BTreeSet<_> = unknown.iter().map(|(id, _, _)| id).collect();
+    ids.into_iter()
+        .map(|id| format!("unknown card {}", id))
+        .collect()
+}
+
+/// Whether a card can be the only commander of a deck
+fn can_lead(card: &DeckCard) -> bool {
+    let front = card.front_type();
+    (front.contains("Legendary") && front.contains("Creature"))
+        || card
+            .abilities()
+            .any(|ability| ability.ends_with("can be your commander."))
+}
+
+/// Whether two cards can be the commanders of the same deck
+fn can_pair(a: &DeckCard, b: &DeckCard) -> bool {
+    let background = |card: &DeckCard| card.front_type().contains("Background");
+    let doctor = |card: &DeckCard| card.front_type().contains("Time Lord Doctor");
+    let ordered = |a: &DeckCard, b: &DeckCard| {
+        (a.has_keyword("Choose a Background") && background(b) && can_lead(a))
+            || (a.has_keyword("Doctor's companion") && doctor(b) && can_lead(b))
+    };
+
+    (a.has_keyword("Partner") && b.has_keyword("Partner"))
+        || (a.has_keyword("Friends forever") && b.has_keyword("Friends forever"))
+        || (a.partner_with() == Some(&b.name) && b.partner_with() == Some(&a.name))
+        || ordered(a, b)
+        || ordered(b, a)
+}
+
+/// Reports every way the deck breaks the commander rules
+pub fn commander(cards: &[DeckCard], unknown: &[Printing]) -> Vec<String> {
+    let mut violations = unknown_cards(unknown);
+    let playing: Vec<_> = cards.iter().filter(|c| c.board != Board::Side).collect();
+    let commanders: Vec<_> = playing
+        .iter()
+        .filter(|c| c.board == Board::Commander)
+        .collect();
+
+    match commanders.as_slice() {
+        [] => violations.push("the deck has no commander".to_string()),
+        [commander] => {
+            if !can_lead(commander) {
+                violations.push(format!("{} cannot be a commander", commander.name));
+            }
+        }
+        [a, b] => {
+            if !can_pair(a, b) {
+                for commander in [a, b] {
+                    if !can_lead(commander) {
+                        violations.push(format!("{} cannot be a commander", commander.name));
+                    }
+                }
+                violations.push(format!(
+                    "{} and {} cannot be commanders together",
+                    a.name, b.name
+                ));
+            }
+        }
+        _ => violations.push(format!(
+            "the deck has {} commanders instead of one or two",
+            commanders.len()
+        )),
+    }
+
+    let size: usize = playing.iter().map(|c| c.count).sum::<usize>()
+        + unknown
+            .iter()
+            .filter(|(_, board, _)| *board != Board::Side)
+            .map(|(_, _, count)| count)
+            .sum::<usize>();
+    if size != 100 {
+        violations.push(format!("the deck has {} cards instead of 100", size));
+    }
+
+    let mut copies: BTreeMap<&str, (&str, usize, Option<usize>)> = BTreeMap::new();
+    for card in &playing {
+        copies
+            .entry(&card.oracle)
+            .or_insert((&card.name, 0, card.copy_limit(1)))
+            .1 += card.count;
+    }
+    for (name, count, limit) in copies.values() {
+        match limit {
+            Some(limit) if count > limit => violations.push(format!(
+                "{} copies of {}, only {} allowed",
+                count, name, limit
+            )),
+            _ => (),
+        }
+    }
+
+    let identity: String = "WUBRG"
+        .chars()
+        .filter(|&color| commanders.iter().any(|c| c.color_identity.contains(color)))
+        .collect();
+    for card in &playing {
+        if !card.color_identity.chars().all(|c| identity.contains(c)) {
+            violations.push(format!(
+                "{} ({}) is outside the color identity of the commander ({})",
+                card.name,
+                card.color_identity,
+                match identity.as_str() {
+                    "" => "C",
+                    identity => identity,
+                }
+            ));
+        }
+    }
+
+    for card in &playing {
+        match card.legality.as_deref() {
+            None => violations.push(format!("{} is not legal in commander", card.name)),
+            Some("banned") => violations.push(format!("{} is banned in commander", card.name)),
+            Some(_) => (),
+        }
+    }
+
+    violations
+}
+
+/// Reports every way the deck breaks the rules of a constructed format: deck and sideboard
+/// sizes, number of copies, banned and restricted cards and, in pauper, rarity
+pub fn constructed(cards: &[DeckCard], unknown: &[Printing], format: &str) -> Vec<String> {
+    let mut violations = unknown_cards(unknown);
+
+    let count = |board| -> usize {
+        let known: usize = cards
+            .iter()
+            .filter(|c| c.board == board)
+            .map(|c| c.count)
+            .sum();
+        let unknown: usize = unknown
+            .iter()
+            .filter(|(_, b, _)| *b == board)
+            .map(|(_, _, count)| count)
+            .sum();
+        known + unknown
+    };
+    let main = count(Board::Main);
+    if main < 60 {
+        violations.push(format!(
+            "the main deck has {} cards, at least 60 are needed",
+            main
+        ));
+    }
+    let side = count(Board::Side);
+    if side > 15 {
+        violations.push(format!(
+            "the sideboard has {} cards, at most 15 are allowed",
+            side
+        ));
+    }
+    let commanders = count(Board::Commander);
+    if commanders > 0 {
+        violations.push(format!(
+            "{} cards are in a commander section, which {} has not",
+            commanders, format
+        ));
+    }
+
+    let mut copies: BTreeMap<&str, (&DeckCard, usize)> = BTreeMap::new();
+    for card in cards {
+        copies.entry(&card.oracle).or_insert((card, 0)).1 += card.count;
+    }
+    for (card, count) in copies.values() {
+        let limit = match card.legality.as_deref() {
+            Some("restricted") => Some(1),
+            _ => card.copy_limit(4),
+        };
+        match limit {
+            Some(limit) if *count > limit => violations.push(format!(
+                "{} copies of {}, only {} allowed",
+                count, card.name, limit
+            )),
+            _ => (),
+        }
+
+        match card.legality.as_deref() {
+            _ if format == "pauper" && !card.common => {
+                violations.push(format!("{} was never printed at common", card.name))
+            }
+            None => violations.push(format!("{} is not legal in {}", card.name, format)),
+            Some("banned") => violations.push(format!("{} is banned in {}", card.name, format)),
+            Some(_) => (),
+        }
+    }
+
+    violations
+}
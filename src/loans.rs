@@ -0,0 +1,342 @@
+use color_eyre::eyre::eyre;
+use rusqlite::{Connection, ToSql};
+use structopt::StructOpt;
+
+use crate::{
+    collection,
+    filter::PrintingFilter,
+    resolve::{Line, Resolver},
+    sqlite,
+};
+
+#[derive(StructOpt)]
+pub enum Args {
+    /// Record copies lent to someone
+    Lend(LendArgs),
+    /// Record copies given back, or everything lent to someone
+    Return(ReturnArgs),
+    /// Show the copies currently lent
+    List(ListArgs),
+    /// Show the loans that are late
+    Overdue(OverdueArgs),
+}
+
+#[derive(StructOpt)]
+pub struct LendArgs {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    /// Cards written like in an add-list file
+    #[structopt(required = true)]
+    cards: Vec<String>,
+    /// Who the cards are lent to
+    #[structopt(long)]
+    to: String,
+    /// Date the cards should be given back, as YYYY-MM-DD
+    #[structopt(long, parse(try_from_str = collection::parse_date))]
+    due: Option<String>,
+    /// Date the cards were lent, as YYYY-MM-DD
+    #[structopt(long, parse(try_from_str = collection::parse_date))]
+    date: Option<String>,
+    /// Whose cards are lent
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    owner: Option<String>,
+    /// Neither use nor remember the printings chosen in previous runs
+    #[structopt(long)]
+    ignore_prefs: bool,
+}
+
+#[derive(StructOpt)]
+pub struct ReturnArgs {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    /// Cards written like in an add-list file, every card lent is returned when none is given
+    cards: Vec<String>,
+    /// Who gives the cards back
+    #[structopt(long)]
+    from: String,
+    /// Date the cards were given back, as YYYY-MM-DD
+    #[structopt(long, parse(try_from_str = collection::parse_date))]
+    date: Option<String>,
+    /// Only return the cards of this owner
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    owner: Option<String>,
+    /// Neither use nor remember the printings chosen in previous runs
+    #[structopt(long)]
+    ignore_prefs: bool,
+}
+
+#[derive(StructOpt)]
+pub struct ListArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Only the cards lent to this person
+    #[structopt(long)]
+    to: Option<String>,
+    /// Also show the loans already returned
+    #[structopt(long)]
+    returned: bool,
+}
+
+#[derive(StructOpt)]
+pub struct OverdueArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Number of days after which loans without a due date are late
+    #[structopt(long, default_value = "30")]
+    days: u32,
+}
+
+impl Args {
+    pub fn loans(self) -> color_eyre::Result<()> {
+        match self {
+            Args::Lend(args) => args.lend(),
+            Args::Return(args) => args.return_cards(),
+            Args::List(args) => args.list(),
+            Args::Overdue(args) => args.overdue(),
+        }
+    }
+}
+
+/// Resolves card lines to `(id, foil, count)`
+fn resolve_cards(
+    db: &Connection,
+    cards: &[String],
+    ignore_prefs: bool,
+) -> color_eyre::Result<Vec<(String, bool, usize)>> {
+    let mut resolver = Resolver::with_prefs(db, &PrintingFilter::default(), ignore_prefs)?;
+    let mut resolved = Vec::new();
+    for card in cards {
+        let entry = match resolver.parse_line(card)? {
+            Line::Card(entry) => entry,
+            Line::Section(_) | Line::Empty => continue,
+        };
+        resolved.push((resolver.resolve(&entry.card)?, entry.foil, entry.count));
+    }
+    resolver.save_prefs()?;
+    Ok(resolved)
+}
+
+fn describe(id: &str, foil: bool) -> String {
+    format!("{}{}", id, if foil { " [F]" } else { "" })
+}
+
+impl LendArgs {
+    fn lend(self) -> color_eyre::Result<()> {
+        let mut db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+        let owner = self.owner.as_deref().unwrap_or_default();
+
+        let tx = db.transaction()?;
+        let mut lent = 0;
+        for (id, foil, count) in resolve_cards(&tx, &self.cards, self.ignore_prefs)? {
+            let available: i64 = tx.query_row(
+                r#"
+                SELECT
+                    (SELECT IFNULL(SUM(amount), 0) FROM cards WHERE id = ?1 AND foil = ?2 AND owner = ?3)
+                    - (
+                        SELECT IFNULL(SUM(amount), 0) FROM loans
+                        WHERE id = ?1 AND foil = ?2 AND owner = ?3 AND returned IS NULL
+                    )"#,
+                rusqlite::params![id, foil, owner],
+                |row| row.get(0),
+            )?;
+            if available < count as i64 {
+                return Err(eyre!(
+                    "cannot lend {} copies of {}, only {} are owned and not lent",
+                    count,
+                    describe(&id, foil),
+                    available.max(0)
+                ));
+            }
+            tx.execute(
+                r#"INSERT INTO loans (id, foil, owner, borrower, amount, date, due)
+                VALUES (?1, ?2, ?3, ?4, ?5, IFNULL(?6, date('now')), ?7)"#,
+                rusqlite::params![id, foil, owner, self.to, count, self.date, self.due],
+            )?;
+            lent += count;
+        }
+        tx.commit()?;
+
+        println!("Lent {} cards to {}", lent, self.to);
+        Ok(())
+    }
+}
+
+impl ReturnArgs {
+    fn return_cards(self) -> color_eyre::Result<()> {
+        let mut db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let tx = db.transaction()?;
+        let returned = if self.cards.is_empty() {
+            let returned = tx.query_row(
+                r#"SELECT IFNULL(SUM(amount), 0) FROM loans
+                WHERE borrower = ?1 AND (?2 IS NULL OR owner = ?2) AND returned IS NULL"#,
+                rusqlite::params![self.from, self.owner],
+                |row| row.get(0),
+            )?;
+            tx.execute(
+                r#"UPDATE loans SET returned = IFNULL(?3, date('now'))
+                WHERE borrower = ?1 AND (?2 IS NULL OR owner = ?2) AND returned IS NULL"#,
+                rusqlite::params![self.from, self.owner, self.date],
+            )?;
+            returned
+        } else {
+            let mut returned = 0;
+            for (id, foil, count) in resolve_cards(&tx, &self.cards, self.ignore_prefs)? {
+                let loans = tx
+                    .prepare(
+                        r#"
+                        SELECT loan, amount FROM loans
+                        WHERE id = ?1 AND foil = ?2 AND borrower = ?3 AND (?4 IS NULL OR owner = ?4)
+                            AND returned IS NULL
+                        ORDER BY date, loan"#,
+                    )?
+                    .query_map(rusqlite::params![id, foil, self.from, self.owner], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?
+                    .collect::<Result<Vec<(i64, usize)>, _>>()?;
+                let lent: usize = loans.iter().map(|(_, amount)| amount).sum();
+                if lent < count {
+                    return Err(eyre!(
+                        "cannot return {} copies of {}, only {} are lent to {}",
+                        count,
+                        describe(&id, foil),
+                        lent,
+                        self.from
+                    ));
+                }
+
+                let mut left = count;
+                for (loan, amount) in loans {
+                    if left == 0 {
+                        break;
+                    }
+                    let taken = left.min(amount);
+                    left -= taken;
+                    // Part of the loan stays open, the returned copies get a loan of their own
+                    tx.execute(
+                        r#"INSERT INTO loans (id, foil, owner, borrower, amount, date, due, returned)
+                        SELECT id, foil, owner, borrower, ?2, date, due, IFNULL(?3, date('now'))
+                        FROM loans WHERE loan = ?1"#,
+                        rusqlite::params![loan, taken, self.date],
+                    )?;
+                    tx.execute(
+                        "UPDATE loans SET amount = amount - ?2 WHERE loan = ?1",
+                        rusqlite::params![loan, taken],
+                    )?;
+                }
+                returned += count;
+            }
+            tx.execute("DELETE FROM loans WHERE amount = 0", [])?;
+            returned
+        };
+        tx.commit()?;
+
+        println!("{} returned {} cards", self.from, returned);
+        Ok(())
+    }
+}
+
+/// Prints the loans matching `condition`, grouped by borrower. `late` is an expression giving
+/// the number of days a loan is late.
+fn print_loans(
+    db: &Connection,
+    condition: &str,
+    late: &str,
+    params: &[&dyn ToSql],
+) -> rusqlite::Result<()> {
+    let mut statement = db.prepare(&format!(
+        r#"
+        SELECT
+            loans.borrower,
+            loans.owner,
+            SUM(loans.amount),
+            scryfall.display_name,
+            upper(scryfall.set_code) || ' ' || scryfall.collector_number,
+            loans.foil,
+            loans.date,
+            loans.due,
+            loans.returned,
+            {late}
+        FROM loans JOIN scryfall ON scryfall.id = loans.id
+        WHERE {condition}
+        GROUP BY loans.borrower, loans.owner, loans.id, loans.foil, loans.date, loans.due, loans.returned
+        ORDER BY loans.borrower, loans.date, scryfall.display_name"#,
+        late = late,
+        condition = condition
+    ))?;
+    let mut rows = statement.query(params)?;
+
+    let mut borrower = None;
+    while let Some(row) = rows.next()? {
+        let lent_to: String = row.get(0)?;
+        if borrower.as_ref() != Some(&lent_to) {
+            println!("{}:", lent_to);
+            borrower = Some(lent_to);
+        }
+
+        let owner: String = row.get(1)?;
+        let amount: usize = row.get(2)?;
+        let name: String = row.get(3)?;
+        let printing: String = row.get(4)?;
+        let foil: bool = row.get(5)?;
+        let date: String = row.get(6)?;
+        let due: Option<String> = row.get(7)?;
+        let returned: Option<String> = row.get(8)?;
+        let late: Option<i64> = row.get(9)?;
+
+        print!(
+            "  {} {} ({}){}",
+            amount,
+            name,
+            printing,
+            if foil { " [F]" } else { "" }
+        );
+        if !owner.is_empty() {
+            print!(" of {}", owner);
+        }
+        print!(", since {}", date);
+        if let Some(due) = due {
+            print!(", due {}", due);
+        }
+        match (returned, late) {
+            (Some(returned), _) => print!(", returned {}", returned),
+            (None, Some(late)) if late > 0 => print!(", {} days late", late),
+            (None, _) => (),
+        }
+        println!();
+    }
+    Ok(())
+}
+
+impl ListArgs {
+    fn list(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        print_loans(
+            &db,
+            "(?1 IS NULL OR loans.borrower = ?1) AND (?2 OR loans.returned IS NULL)",
+            "CAST(julianday('now') - julianday(loans.due) AS INTEGER)",
+            &[&self.to, &self.returned],
+        )?;
+        Ok(())
+    }
+}
+
+impl OverdueArgs {
+    fn overdue(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let due = "IFNULL(loans.due, date(loans.date, '+' || ?1 || ' days'))";
+        print_loans(
+            &db,
+            &format!("loans.returned IS NULL AND {} < date('now')", due),
+            &format!("CAST(julianday('now') - julianday({}) AS INTEGER)", due),
+            &[&self.days],
+        )?;
+        Ok(())
+    }
+}
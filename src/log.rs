@@ -0,0 +1,114 @@
+use rusqlite::Connection;
+use structopt::StructOpt;
+
+use crate::{price::Currency, sqlite};
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Only show the sales and trades made with this person
+    #[structopt(long = "with")]
+    counterparty: Option<String>,
+}
+
+/// Cards of one side of an exchange, as `count name (SET number) at price`
+fn exchanged(
+    db: &Connection,
+    table: &str,
+    exchange: i64,
+    currency: Currency,
+) -> rusqlite::Result<Vec<String>> {
+    // Acquisitions only keep the number of copies still held in `amount`
+    let count = match table {
+        "acquisitions" => "bought",
+        _ => "amount",
+    };
+    let cards = db
+        .prepare(&format!(
+            r#"
+            SELECT
+                SUM({table}.{count}),
+                IFNULL(scryfall.display_name, {table}.id),
+                upper(scryfall.set_code) || ' ' || scryfall.collector_number,
+                {table}.foil,
+                {table}.price
+            FROM {table} LEFT JOIN scryfall ON scryfall.id = {table}.id
+            WHERE {table}.exchange = ?1
+            GROUP BY {table}.id, {table}.foil, {table}.price
+            ORDER BY 2"#,
+            table = table,
+            count = count
+        ))?
+        .query_map([exchange], |row| {
+            let count: usize = row.get(0)?;
+            let name: String = row.get(1)?;
+            let printing: Option<String> = row.get(2)?;
+            let foil: bool = row.get(3)?;
+            let price: Option<f64> = row.get(4)?;
+            Ok(format!(
+                "{} {}{}{}{}",
+                count,
+                name,
+                printing.map(|p| format!(" ({})", p)).unwrap_or_default(),
+                if foil { " [F]" } else { "" },
+                price
+                    .map(|p| format!(" at {}", currency.format(p)))
+                    .unwrap_or_default()
+            ))
+        })?
+        .collect();
+    cards
+}
+
+impl Args {
+    pub fn log(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let mut statement = db.prepare(
+            r#"
+            SELECT exchange, kind, counterparty, reason, cash, currency, date
+            FROM exchanges
+            WHERE ?1 IS NULL OR counterparty = ?1
+            ORDER BY date, exchange"#,
+        )?;
+        let mut rows = statement.query([&self.counterparty])?;
+        while let Some(row) = rows.next()? {
+            let exchange: i64 = row.get(0)?;
+            let kind: String = row.get(1)?;
+            let counterparty: Option<String> = row.get(2)?;
+            let reason: Option<String> = row.get(3)?;
+            let cash: Option<f64> = row.get(4)?;
+            let currency: Currency = row.get::<_, String>(5)?.parse()?;
+            let date: String = row.get(6)?;
+
+            print!("{} {}", date, kind);
+            if let Some(counterparty) = counterparty {
+                print!(
+                    " {} {}",
+                    if kind == "sale" { "to" } else { "with" },
+                    counterparty
+                );
+            }
+            if let Some(reason) = reason {
+                print!(" ({})", reason);
+            }
+            match cash {
+                Some(c) if c > 0. => print!(", {} received", currency.format(c)),
+                Some(c) if c < 0. => print!(", {} paid", currency.format(-c)),
+                _ => (),
+            }
+            println!();
+
+            for card in exchanged(&db, "disposals", exchange, currency)? {
+                println!("  - {}", card);
+            }
+            for card in exchanged(&db, "acquisitions", exchange, currency)? {
+                println!("  + {}", card);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,9 +1,37 @@
 use structopt::StructOpt;
 
 mod add_list;
+mod check;
+mod collection;
+mod deck;
 mod dump;
+mod export;
+mod filter;
+mod images;
 mod jsonl;
+mod legality;
+mod loans;
+mod log;
+mod mana;
+mod owners;
+mod picker;
+mod pnl;
+mod prefs;
+mod price;
+mod products;
+mod proxies;
+mod remove_list;
+mod resolve;
+mod search;
+mod serve;
+mod sets;
 mod sqlite;
+mod stats;
+mod surplus;
+mod tags;
+mod trade;
+mod value;
+mod wants;
 
 #[derive(StructOpt)]
 struct Args {
@@ -14,8 +42,29 @@ struct Args {
 #[derive(StructOpt)]
 enum Commands {
     AddList(add_list::Args),
+    Check(check::Args),
+    Deck(deck::Args),
     Dump(dump::Args),
+    Export(export::Args),
+    Images(images::Args),
     Jsonl(jsonl::Args),
+    Loans(loans::Args),
+    Log(log::Args),
+    Owners(owners::Args),
+    Pnl(pnl::Args),
+    Prefs(prefs::Args),
+    Products(products::Args),
+    Proxies(proxies::Args),
+    RemoveList(remove_list::Args),
+    Search(search::Args),
+    Serve(serve::Args),
+    Sets(sets::Args),
+    Stats(stats::Args),
+    Surplus(surplus::Args),
+    Tags(tags::Args),
+    Trade(trade::Args),
+    Value(value::Args),
+    Wants(wants::Args),
 }
 
 fn main() -> color_eyre::Result<()> {
@@ -24,7 +73,28 @@ fn main() -> color_eyre::Result<()> {
     let args = Args::from_args();
     match args.commands {
         Commands::AddList(sub_args) => sub_args.add_list(),
+        Commands::Check(sub_args) => sub_args.check(),
+        Commands::Deck(sub_args) => sub_args.deck(),
         Commands::Dump(sub_args) => sub_args.dump_scryfall(),
+        Commands::Export(sub_args) => sub_args.export(),
+        Commands::Images(sub_args) => sub_args.images(),
         Commands::Jsonl(sub_args) => sub_args.convert_jsonl(),
+        Commands::Loans(sub_args) => sub_args.loans(),
+        Commands::Log(sub_args) => sub_args.log(),
+        Commands::Owners(sub_args) => sub_args.owners(),
+        Commands::Pnl(sub_args) => sub_args.pnl(),
+        Commands::Prefs(sub_args) => sub_args.prefs(),
+        Commands::Products(sub_args) => sub_args.products(),
+        Commands::Proxies(sub_args) => sub_args.proxies(),
+        Commands::RemoveList(sub_args) => sub_args.remove_list(),
+        Commands::Search(sub_args) => sub_args.search(),
+        Commands::Serve(sub_args) => sub_args.serve(),
+        Commands::Sets(sub_args) => sub_args.sets(),
+        Commands::Stats(sub_args) => sub_args.stats(),
+        Commands::Surplus(sub_args) => sub_args.surplus(),
+        Commands::Tags(sub_args) => sub_args.tags(),
+        Commands::Trade(sub_args) => sub_args.trade(),
+        Commands::Value(sub_args) => sub_args.value(),
+        Commands::Wants(sub_args) => sub_args.wants(),
     }
 }
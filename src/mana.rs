@@ -0,0 +1,296 @@
+use color_eyre::eyre::eyre;
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+/// Hybrid pairs in the order they are printed
+const PAIRS: &[&str] = &["WU", "UB", "BR", "RG", "GW", "WB", "UR", "BG", "RW", "GU"];
+
+/// Kinds of mana symbols, as stored in the `scryfall_mana_symbols` table
+pub const KINDS: &[&str] = &[
+    "generic",
+    "variable",
+    "colored",
+    "colorless",
+    "snow",
+    "hybrid",
+    "phyrexian",
+    "half",
+    "other",
+];
+
+/// A symbol of a mana cost
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Symbol {
+    /// `{1}`, a generic cost like `{3}` being three of them
+    Generic,
+    /// `{0}`, kept apart so that free spells differ from cards without a cost
+    Zero,
+    /// `{X}`, `{Y}` or `{Z}`
+    Variable(char),
+    /// `{W}`, `{U}`, `{B}`, `{R}` or `{G}`
+    Colored(char),
+    /// `{C}`
+    Colorless,
+    /// `{S}`
+    Snow,
+    /// `{W/U}`, or `{2/W}` and `{C/W}` when one half is generic or colorless
+    Hybrid(char, char),
+    /// `{W/P}`, or `{W/U/P}` for hybrid Phyrexian mana
+    Phyrexian(char, Option<char>),
+    /// `{HW}`, or `{½}` for half of a generic mana
+    Half(Option<char>),
+    /// Symbols of the silver bordered cards like `{∞}`
+    Other(String),
+}
+
+fn is_color(c: char) -> bool {
+    "WUBRG".contains(c)
+}
+
+/// Orders the halves of a hybrid symbol like they are printed, generic or colorless first
+fn pair(a: char, b: char) -> (char, char) {
+    let reversed: String = [b, a].iter().collect();
+    if !is_color(b) || (is_color(a) && PAIRS.contains(&reversed.as_str())) {
+        (b, a)
+    } else {
+        (a, b)
+    }
+}
+
+impl Symbol {
+    /// Parses the inside of the braces of a symbol, along with the number of times it counts
+    fn parse(symbol: &str) -> (Self, usize) {
+        let symbol = symbol.trim().to_uppercase();
+        let parts: Vec<_> = symbol.split('/').collect();
+        let one = |part: &str| {
+            let mut chars = part.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => None,
+            }
+        };
+
+        if let Ok(amount) = symbol.parse() {
+            return match amount {
+                0 => (Symbol::Zero, 1),
+                amount => (Symbol::Generic, amount),
+            };
+        }
+
+        let parsed = match parts.as_slice() {
+            ["½"] => Some(Symbol::Half(None)),
+            [half] if half.starts_with('H') => one(&half[1..])
+                .filter(|&c| is_color(c))
+                .map(|c| Symbol::Half(Some(c))),
+            [single] => one(single).and_then(|c| match c {
+                'X' | 'Y' | 'Z' => Some(Symbol::Variable(c)),
+                'C' => Some(Symbol::Colorless),
+                'S' => Some(Symbol::Snow),
+                c if is_color(c) => Some(Symbol::Colored(c)),
+                _ => None,
+            }),
+            [color, "P"] => one(color)
+                .filter(|&c| is_color(c))
+                .map(|c| Symbol::Phyrexian(c, None)),
+            [a, b, "P"] => match (one(a), one(b)) {
+                (Some(a), Some(b)) if is_color(a) && is_color(b) && a != b => {
+                    let (a, b) = pair(a, b);
+                    Some(Symbol::Phyrexian(a, Some(b)))
+                }
+                _ => None,
+            },
+            [a, b] => match (one(a), one(b)) {
+                (Some(a), Some(b))
+                    if a != b
+                        && (is_color(a) || "2C".contains(a))
+                        && (is_color(b) || "2C".contains(b))
+                        && (is_color(a) || is_color(b)) =>
+                {
+                    let (a, b) = pair(a, b);
+                    Some(Symbol::Hybrid(a, b))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        (parsed.unwrap_or(Symbol::Other(symbol)), 1)
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Symbol::Generic | Symbol::Zero => "generic",
+            Symbol::Variable(_) => "variable",
+            Symbol::Colored(_) => "colored",
+            Symbol::Colorless => "colorless",
+            Symbol::Snow => "snow",
+            Symbol::Hybrid(_, _) => "hybrid",
+            Symbol::Phyrexian(_, _) => "phyrexian",
+            Symbol::Half(_) => "half",
+            Symbol::Other(_) => "other",
+        }
+    }
+
+    /// Colors the symbol can be paid with, `C` standing for colorless mana. `{C/W}` needs either
+    /// colorless mana or white, so it counts for both.
+    pub fn colors(&self) -> Vec<char> {
+        match *self {
+            Symbol::Colored(c) | Symbol::Half(Some(c)) | Symbol::Phyrexian(c, None) => vec![c],
+            Symbol::Colorless => vec!['C'],
+            Symbol::Hybrid(a, b) => [a, b].iter().copied().filter(|&c| c != '2').collect(),
+            Symbol::Phyrexian(a, Some(b)) => vec![a, b],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Symbol::Generic => write!(f, "{{1}}"),
+            Symbol::Zero => write!(f, "{{0}}"),
+            Symbol::Variable(c) | Symbol::Colored(c) => write!(f, "{{{}}}", c),
+            Symbol::Colorless => write!(f, "{{C}}"),
+            Symbol::Snow => write!(f, "{{S}}"),
+            Symbol::Hybrid(a, b) => write!(f, "{{{}/{}}}", a, b),
+            Symbol::Phyrexian(c, None) => write!(f, "{{{}/P}}", c),
+            Symbol::Phyrexian(a, Some(b)) => write!(f, "{{{}/{}/P}}", a, b),
+            Symbol::Half(None) => write!(f, "{{½}}"),
+            Symbol::Half(Some(c)) => write!(f, "{{H{}}}", c),
+            Symbol::Other(symbol) => write!(f, "{{{}}}", symbol),
+        }
+    }
+}
+
+/// A mana cost, with the number of each of its symbols. The halves of split cards
+/// (`{1}{R} // {1}{U}`) are taken together.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Cost(pub BTreeMap<Symbol, usize>);
+
+impl FromStr for Cost {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut symbols = BTreeMap::new();
+        let mut rest = s.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        while !rest.is_empty() {
+            let (symbol, after) = rest
+                .strip_prefix('{')
+                .and_then(|inside| inside.split_once('}'))
+                .ok_or_else(|| eyre!("{} is not a mana cost like {{2}}{{W}}{{W}}", s))?;
+            let (symbol, amount) = Symbol::parse(symbol);
+            *symbols.entry(symbol).or_default() += amount;
+            rest = after.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        }
+        Ok(Cost(symbols))
+    }
+}
+
+impl Cost {
+    /// Number of symbols of each color, `C` counting the colorless ones. Hybrid symbols count
+    /// for each of their colors.
+    pub fn pips(&self) -> BTreeMap<char, usize> {
+        let mut pips = BTreeMap::new();
+        for (symbol, amount) in &self.0 {
+            for color in symbol.colors() {
+                *pips.entry(color).or_default() += amount;
+            }
+        }
+        pips
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost(s: &str) -> Cost {
+        s.parse().unwrap()
+    }
+
+    /// Symbols of a cost written back in their canonical form
+    fn canonical(s: &str) -> String {
+        cost(s)
+            .0
+            .iter()
+            .flat_map(|(symbol, &amount)| std::iter::repeat_n(symbol.to_string(), amount))
+            .collect()
+    }
+
+    #[test]
+    fn generic_costs() {
+        let big = cost("{12}{G}");
+        assert_eq!(big.0[&Symbol::Generic], 12);
+        assert_eq!(big.0[&Symbol::Colored('G')], 1);
+        assert_eq!(cost("{0}").0[&Symbol::Zero], 1);
+        assert_eq!(cost("").0.len(), 0);
+        assert_eq!(cost("{X}{X}{R}").0[&Symbol::Variable('X')], 2);
+    }
+
+    #[test]
+    fn hybrid_pairs_are_ordered() {
+        assert_eq!(cost("{U/W}"), cost("{W/U}"));
+        assert_eq!(canonical("{U/W}"), "{W/U}");
+        assert_eq!(canonical("{W/R}"), "{R/W}");
+        assert_eq!(canonical("{g/u}"), "{G/U}");
+        assert_eq!(canonical("{W/2}"), "{2/W}");
+        assert_eq!(canonical("{W/C}"), "{C/W}");
+        assert_eq!(canonical("{U/G/P}"), "{G/U/P}");
+    }
+
+    #[test]
+    fn symbol_kinds() {
+        let kinds = |s: &str| -> Vec<&str> { cost(s).0.keys().map(Symbol::kind).collect() };
+        assert_eq!(kinds("{2/W}"), ["hybrid"]);
+        assert_eq!(kinds("{C/W}"), ["hybrid"]);
+        assert_eq!(kinds("{W/P}"), ["phyrexian"]);
+        assert_eq!(kinds("{G/U/P}"), ["phyrexian"]);
+        assert_eq!(kinds("{HW}"), ["half"]);
+        assert_eq!(kinds("{½}"), ["half"]);
+        assert_eq!(kinds("{C}{S}"), ["colorless", "snow"]);
+        assert_eq!(kinds("{∞}"), ["other"]);
+        // Pairs that are not mana are kept as they are written
+        assert_eq!(kinds("{W/W}"), ["other"]);
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for written in [
+            "{3}{W}{W}",
+            "{2/W}{2/W}{2/W}",
+            "{C/W}",
+            "{W/U}{U/B}",
+            "{G/U/P}",
+            "{B/P}{B/P}",
+            "{HW}",
+            "{½}",
+            "{X}{R}",
+            "{0}",
+            "{C}{S}",
+        ] {
+            let shown = canonical(written);
+            assert_eq!(cost(&shown), cost(written), "{}", written);
+            assert_eq!(canonical(&shown), shown, "{}", written);
+        }
+    }
+
+    #[test]
+    fn split_cards_are_taken_together() {
+        assert_eq!(cost("{1}{R} // {1}{U}"), cost("{2}{R}{U}"));
+    }
+
+    #[test]
+    fn invalid_costs() {
+        assert!("2WW".parse::<Cost>().is_err());
+        assert!("{W".parse::<Cost>().is_err());
+    }
+
+    #[test]
+    fn pips() {
+        let pips = |s: &str| -> Vec<(char, usize)> { cost(s).pips().into_iter().collect() };
+        assert_eq!(pips("{2}{W}{W}"), [('W', 2)]);
+        assert_eq!(pips("{W/U}{W}"), [('U', 1), ('W', 2)]);
+        assert_eq!(pips("{2/W}"), [('W', 1)]);
+        assert_eq!(pips("{C/W}"), [('C', 1), ('W', 1)]);
+        assert_eq!(pips("{G/U/P}{HW}"), [('G', 1), ('U', 1), ('W', 1)]);
+        assert_eq!(pips("{X}{C}"), [('C', 1)]);
+    }
+}
@@ -0,0 +1,218 @@
+use color_eyre::eyre::eyre;
+use rusqlite::Connection;
+use structopt::StructOpt;
+
+use crate::{
+    collection, deck,
+    filter::{CardFilter, PrintingFilter},
+    price::Currency,
+    resolve::{Line, Resolver},
+    sqlite, value,
+};
+
+#[derive(StructOpt)]
+pub enum Args {
+    /// Show the owners with the number and value of their cards
+    List(ListArgs),
+    /// Give the cards of an owner, or the cards without one, to another owner
+    Assign(AssignArgs),
+    /// Show which owners have some cards, or the cards of a deck
+    WhoHas(WhoHasArgs),
+}
+
+#[derive(StructOpt)]
+pub struct ListArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+}
+
+#[derive(StructOpt)]
+pub struct AssignArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    owner: String,
+    /// Current owner of the cards, the cards without an owner are given by default
+    #[structopt(long, default_value = "")]
+    from: String,
+}
+
+#[derive(StructOpt)]
+pub struct WhoHasArgs {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    /// Cards written like in an add-list file, every printing of them is counted
+    #[structopt(required_unless = "deck")]
+    cards: Vec<String>,
+    /// Show the owners of the cards of this saved deck
+    #[structopt(long, conflicts_with = "cards")]
+    deck: Option<String>,
+    /// Only show the cards this owner is missing, and the other owners having them
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    owner: Option<String>,
+}
+
+impl Args {
+    pub fn owners(self) -> color_eyre::Result<()> {
+        match self {
+            Args::List(args) => args.list(),
+            Args::Assign(args) => args.assign(),
+            Args::WhoHas(args) => args.who_has(),
+        }
+    }
+}
+
+impl ListArgs {
+    fn list(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let owners = db
+            .prepare("SELECT DISTINCT owner FROM cards WHERE amount > 0 ORDER BY owner")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        for owner in owners {
+            let filter = CardFilter {
+                owner: Some(owner.clone()),
+                ..Default::default()
+            };
+            let valuation = value::value(&db, &filter, self.currency)?;
+            println!(
+                "  {}: {} cards worth {}",
+                collection::owner_name(&owner),
+                valuation.cards,
+                self.currency.format(valuation.value)
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl AssignArgs {
+    fn assign(self) -> color_eyre::Result<()> {
+        if self.owner == self.from {
+            return Err(eyre!("the cards already belong to {}", self.owner));
+        }
+
+        let mut db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let tx = db.transaction()?;
+        let moved: usize = tx.query_row(
+            "SELECT IFNULL(SUM(amount), 0) FROM cards WHERE owner = ?1",
+            [&self.from],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            r#"INSERT INTO cards (id, foil, owner, amount)
+            SELECT id, foil, ?2, amount FROM cards WHERE owner = ?1
+            ON CONFLICT (id, foil, owner) DO UPDATE SET amount = amount + excluded.amount"#,
+            [&self.from, &self.owner],
+        )?;
+        tx.execute("DELETE FROM cards WHERE owner = ?1", [&self.from])?;
+        tx.execute(
+            r#"INSERT INTO card_tags (id, foil, owner, tag, amount)
+            SELECT id, foil, ?2, tag, amount FROM card_tags WHERE owner = ?1
+            ON CONFLICT (id, foil, owner, tag) DO UPDATE SET amount = amount + excluded.amount"#,
+            [&self.from, &self.owner],
+        )?;
+        tx.execute("DELETE FROM card_tags WHERE owner = ?1", [&self.from])?;
+        for table in &["lists", "acquisitions", "card_notes", "loans"] {
+            tx.execute(
+                &format!("UPDATE {} SET owner = ?2 WHERE owner = ?1", table),
+                [&self.from, &self.owner],
+            )?;
+        }
+        tx.commit()?;
+
+        println!(
+            "Gave {} cards of {} to {}",
+            moved,
+            collection::owner_name(&self.from),
+            self.owner
+        );
+        Ok(())
+    }
+}
+
+/// Owners having copies of a card, with their number of copies and how many of them are foil
+fn holders(db: &Connection, oracle: &str) -> rusqlite::Result<Vec<(String, usize, usize)>> {
+    db.prepare(
+        r#"
+        SELECT cards.owner, SUM(cards.amount), SUM(IIF(cards.foil, cards.amount, 0))
+        FROM cards JOIN scryfall ON scryfall.id = cards.id
+        WHERE (scryfall.oracle_id = ?1 OR scryfall.id = ?1) AND cards.amount > 0
+        GROUP BY cards.owner
+        ORDER BY SUM(cards.amount) DESC, cards.owner"#,
+    )?
+    .query_map([oracle], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+    .collect()
+}
+
+impl WhoHasArgs {
+    fn who_has(self) -> color_eyre::Result<()> {
+        let db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        // Oracle id, name and number of copies needed of each card
+        let cards: Vec<(String, String, usize)> = match &self.deck {
+            Some(deck) => deck::check_deck(&db, deck, None, Currency::Eur)?
+                .into_iter()
+                .map(|card| (card.oracle, card.name, card.needed))
+                .collect(),
+            None => {
+                let mut resolver = Resolver::new(&db, &PrintingFilter::default())?;
+                let mut cards = Vec::new();
+                for card in &self.cards {
+                    let entry = match resolver.parse_line(card)? {
+                        Line::Card(entry) => entry,
+                        Line::Section(_) | Line::Empty => continue,
+                    };
+                    let oracle = resolver.resolve_oracle(&entry.card)?;
+                    let name = db.query_row(
+                        "SELECT MIN(display_name) FROM scryfall WHERE oracle_id = ?1 OR id = ?1",
+                        [&oracle],
+                        |row| row.get(0),
+                    )?;
+                    cards.push((oracle, name, entry.count));
+                }
+                cards
+            }
+        };
+
+        for (oracle, name, needed) in cards {
+            let mut holders = holders(&db, &oracle)?;
+            let needed = match &self.owner {
+                Some(owner) => {
+                    let owned = holders
+                        .iter()
+                        .find(|(holder, _, _)| holder == owner)
+                        .map(|&(_, amount, _)| amount)
+                        .unwrap_or(0);
+                    holders.retain(|(holder, _, _)| holder != owner);
+                    match needed.saturating_sub(owned) {
+                        0 => continue,
+                        missing => missing,
+                    }
+                }
+                None => needed,
+            };
+
+            println!("{} {}:", needed, name);
+            if holders.is_empty() {
+                println!("  nobody");
+            }
+            for (holder, amount, foil) in holders {
+                print!("  {}: {}", collection::owner_name(&holder), amount);
+                if foil > 0 {
+                    print!(" ({} foil)", foil);
+                }
+                println!();
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use color_eyre::eyre::eyre;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+    tty::IsTty,
+};
+use std::io::{self, Stdout};
+use tui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Terminal,
+};
+
+use crate::resolve::{CardInfo, Choice};
+
+/// The full screen picker needs both ends of the terminal
+pub fn is_available() -> bool {
+    io::stdin().is_tty() && io::stdout().is_tty()
+}
+
+/// Restores the terminal when the picker exits, even on errors
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl Screen {
+    fn open() -> color_eyre::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        terminal.hide_cursor()?;
+        Ok(Self { terminal })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+struct Picker<'a> {
+    cards: &'a [CardInfo],
+    /// Text typed to filter on the set name or code
+    filter: String,
+    /// Indices of the cards matching the filter
+    shown: Vec<usize>,
+    state: ListState,
+}
+
+impl<'a> Picker<'a> {
+    fn new(cards: &'a [CardInfo]) -> Self {
+        let mut picker = Self {
+            cards,
+            filter: String::new(),
+            shown: Vec::new(),
+            state: ListState::default(),
+        };
+        picker.refilter();
+        picker
+    }
+
+    fn refilter(&mut self) {
+        let filter = self.filter.to_lowercase();
+        self.shown = self
+            .cards
+            .iter()
+            .enumerate()
+            .filter(|(_, card)| {
+                card.set.to_lowercase().contains(&filter) || card.set_code.contains(&filter)
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+        self.state
+            .select(if self.shown.is_empty() { None } else { Some(0) });
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.state.selected().map(|s| self.shown[s])
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        if let Some(selected) = self.state.selected() {
+            let last = self.shown.len() as isize - 1;
+            let selected = (selected as isize + offset).max(0).min(last);
+            self.state.select(Some(selected as usize));
+        }
+    }
+
+    fn draw(&mut self, frame: &mut tui::Frame<CrosstermBackend<Stdout>>, choice: &str, undo: bool) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(5),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(rows[1]);
+
+        let filter = Paragraph::new(format!("Set: {}", self.filter)).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Choose match for {}", choice)),
+        );
+        frame.render_widget(filter, rows[0]);
+
+        let items: Vec<ListItem> = self
+            .shown
+            .iter()
+            .map(|&idx| {
+                let card = &self.cards[idx];
+                ListItem::new(format!(
+                    "{} - {} ({}) #{}{}",
+                    card.name,
+                    card.set,
+                    card.set_code.to_uppercase(),
+                    card.collector_number,
+                    if card.promo { " (promo)" } else { "" }
+                ))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "{}/{} printings",
+                self.shown.len(),
+                self.cards.len()
+            )))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, columns[0], &mut self.state);
+
+        let details = match self.selected() {
+            None => vec![Spans::from("No printing matches the filter")],
+            Some(idx) => {
+                let card = &self.cards[idx];
+                let price = |price: Option<f64>, format: fn(f64) -> String| {
+                    price.map(format).unwrap_or_else(|| "-".into())
+                };
+                let line = |label: &str, value: String| {
+                    Spans::from(vec![
+                        Span::styled(
+                            format!("{}: ", label),
+                            Style::default().add_modifier(Modifier::BOLD),
+                        ),
+                        Span::raw(value),
+                    ])
+                };
+                let mut lines = vec![line("Name", card.name.clone())];
+                if let Some(face) = &card.face {
+                    lines.push(line("Matched face", face.clone()));
+                }
+                lines.extend(vec![
+                    line(
+                        "Set",
+                        format!("{} ({})", card.set, card.set_code.to_uppercase()),
+                    ),
+                    line("Number", card.collector_number.clone()),
+                    line("Rarity", card.rarity.clone()),
+                    line(
+                        "Mana cost",
+                        card.mana_cost.clone().unwrap_or_else(|| "-".into()),
+                    ),
+                    line("Price", price(card.eur, |p| format!("{:.2}€", p))),
+                    line("Price (USD)", price(card.usd, |p| format!("${:.2}", p))),
+                    line("Layout", card.layout.clone()),
+                    line("Promo", if card.promo { "yes" } else { "no" }.into()),
+                    line("Match score", card.score.to_string()),
+                    line("Link", card.uri.clone()),
+                ]);
+                lines
+            }
+        };
+        let details = Paragraph::new(details)
+            .block(Block::default().borders(Borders::ALL).title("Details"))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(details, columns[1]);
+
+        let help = format!(
+            "↑/↓ move  Enter choose  type to filter by set  Esc skip{}  Ctrl-C abort",
+            if undo { "  Ctrl-Z undo previous" } else { "" }
+        );
+        frame.render_widget(Paragraph::new(help), rows[2]);
+    }
+}
+
+/// Lets the user pick one of `cards` in a full screen interface, returning its index
+pub fn pick(choice: &str, cards: &[CardInfo], undo: bool) -> color_eyre::Result<Choice<usize>> {
+    let mut screen = Screen::open()?;
+    let mut picker = Picker::new(cards);
+
+    loop {
+        screen
+            .terminal
+            .draw(|frame| picker.draw(frame, choice, undo))?;
+
+        let KeyEvent { code, modifiers } = match event::read()? {
+            Event::Key(key) => key,
+            _ => continue,
+        };
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+        match code {
+            KeyCode::Char('c') if ctrl => {
+                return Err(eyre!("interrupted while choosing {}", choice))
+            }
+            KeyCode::Char('z') if ctrl && undo => return Ok(Choice::Undo),
+            KeyCode::Esc if !picker.filter.is_empty() => {
+                picker.filter.clear();
+                picker.refilter();
+            }
+            KeyCode::Esc => return Ok(Choice::Skip),
+            KeyCode::Enter => {
+                if let Some(idx) = picker.selected() {
+                    return Ok(Choice::Chosen(idx));
+                }
+            }
+            KeyCode::Up => picker.move_by(-1),
+            KeyCode::Down => picker.move_by(1),
+            KeyCode::PageUp => picker.move_by(-10),
+            KeyCode::PageDown => picker.move_by(10),
+            KeyCode::Home => picker.move_by(-(cards.len() as isize)),
+            KeyCode::End => picker.move_by(cards.len() as isize),
+            KeyCode::Backspace => {
+                picker.filter.pop();
+                picker.refilter();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                picker.filter.push(c);
+                picker.refilter();
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+use crate::{price::Currency, sqlite};
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Group the report by card or by the list the cards were bought with
+    #[structopt(long, default_value = "card", possible_values = &["card", "list"])]
+    by: String,
+    /// Only the purchases made in this currency are taken into account
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+}
+
+/// Profit and loss of a group of acquisitions
+#[derive(Default, Debug)]
+struct Pnl {
+    held: usize,
+    /// Price paid for the held copies with a known price
+    cost: f64,
+    /// Current value of the held copies with a known price
+    value: f64,
+    /// Held copies without a purchase or a current price
+    unknown: usize,
+    sold: usize,
+    proceeds: f64,
+    /// Price paid for the sold copies
+    sold_cost: f64,
+    /// Copies removed without being sold
+    removed: usize,
+}
+
+impl Pnl {
+    fn add(&mut self, other: &Pnl) {
+        self.held += other.held;
+        self.cost += other.cost;
+        self.value += other.value;
+        self.unknown += other.unknown;
+        self.sold += other.sold;
+        self.proceeds += other.proceeds;
+        self.sold_cost += other.sold_cost;
+        self.removed += other.removed;
+    }
+
+    fn describe(&self, currency: Currency) -> String {
+        let mut parts = Vec::new();
+        if self.held > 0 {
+            parts.push(format!(
+                "{} held, cost {}, worth {} ({})",
+                self.held,
+                currency.format(self.cost),
+                currency.format(self.value),
+                signed(currency, self.value - self.cost)
+            ));
+        }
+        if self.unknown > 0 {
+            parts.push(format!("{} without a price", self.unknown));
+        }
+        if self.sold > 0 {
+            parts.push(format!(
+                "{} sold for {} ({})",
+                self.sold,
+                currency.format(self.proceeds),
+                signed(currency, self.proceeds - self.sold_cost)
+            ));
+        }
+        if self.removed > 0 {
+            parts.push(format!("{} removed", self.removed));
+        }
+        parts.join(", ")
+    }
+}
+
+fn signed(currency: Currency, amount: f64) -> String {
+    match amount >= 0. {
+        true => format!("+{}", currency.format(amount)),
+        false => currency.format(amount),
+    }
+}
+
+fn pnl(db: &Connection, by: &str, currency: Currency) -> rusqlite::Result<BTreeMap<String, Pnl>> {
+    let key = match by {
+        "list" => "IFNULL(lists.name, IFNULL(lower(hex(acquisitions.hash)), '<no list>'))",
+        _ => "scryfall.display_name",
+    };
+    let mut report: BTreeMap<String, Pnl> = BTreeMap::new();
+
+    let mut held = db.prepare(&format!(
+        r#"
+        SELECT {key}, acquisitions.amount, acquisitions.price, {market}
+        FROM
+            acquisitions
+            JOIN scryfall ON scryfall.id = acquisitions.id
+            LEFT JOIN lists ON lists.hash = acquisitions.hash
+        WHERE acquisitions.amount > 0 AND acquisitions.currency = ?1"#,
+        key = key,
+        market = currency.price_expr("acquisitions.foil")
+    ))?;
+    let mut rows = held.query([currency.as_str()])?;
+    while let Some(row) = rows.next()? {
+        let entry = report.entry(row.get(0)?).or_default();
+        let amount: usize = row.get(1)?;
+        entry.held += amount;
+        match (row.get::<_, Option<f64>>(2)?, row.get::<_, Option<f64>>(3)?) {
+            (Some(cost), Some(value)) => {
+                entry.cost += cost * amount as f64;
+                entry.value += value * amount as f64;
+            }
+            _ => entry.unknown += amount,
+        }
+    }
+
+    // Copies owned before purchases were tracked have no cost to compare with
+    let mut sold = db.prepare(&format!(
+        r#"
+        SELECT {key}, disposals.amount, disposals.price, acquisitions.price
+        FROM
+            disposals
+            JOIN acquisitions ON acquisitions.acquisition = disposals.acquisition
+            JOIN scryfall ON scryfall.id = disposals.id
+            LEFT JOIN lists ON lists.hash = acquisitions.hash
+        WHERE acquisitions.currency = ?1 AND disposals.currency = ?1
+            AND disposals.price IS NOT NULL AND acquisitions.price IS NOT NULL"#,
+        key = key
+    ))?;
+    let mut rows = sold.query([currency.as_str()])?;
+    while let Some(row) = rows.next()? {
+        let entry = report.entry(row.get(0)?).or_default();
+        let amount: usize = row.get(1)?;
+        let proceeds: f64 = row.get(2)?;
+        let cost: f64 = row.get(3)?;
+        entry.sold += amount;
+        entry.proceeds += proceeds * amount as f64;
+        entry.sold_cost += cost * amount as f64;
+    }
+
+    // Copies lost or thrown away were not sold, they have no proceeds
+    let mut removed = db.prepare(&format!(
+        r#"
+        SELECT {key}, disposals.amount
+        FROM
+            disposals
+            JOIN acquisitions ON acquisitions.acquisition = disposals.acquisition
+            JOIN scryfall ON scryfall.id = disposals.id
+            LEFT JOIN lists ON lists.hash = acquisitions.hash
+        WHERE acquisitions.currency = ?1 AND disposals.price IS NULL"#,
+        key = key
+    ))?;
+    let mut rows = removed.query([currency.as_str()])?;
+    while let Some(row) = rows.next()? {
+        let entry = report.entry(row.get(0)?).or_default();
+        entry.removed += row.get::<_, usize>(1)?;
+    }
+
+    Ok(report)
+}
+
+impl Args {
+    pub fn pnl(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let report = pnl(&db, &self.by, self.currency)?;
+        let mut total = Pnl::default();
+        for (key, entry) in &report {
+            println!("  {}: {}", key, entry.describe(self.currency));
+            total.add(entry);
+        }
+        println!("Total: {}", total.describe(self.currency));
+
+        let untracked: i64 = db.query_row(
+            r#"SELECT
+                (SELECT IFNULL(SUM(amount), 0) FROM cards)
+                - (SELECT IFNULL(SUM(amount), 0) FROM acquisitions)"#,
+            [],
+            |row| row.get(0),
+        )?;
+        if untracked > 0 {
+            println!(
+                "{} owned cards were added before purchases were recorded",
+                untracked
+            );
+        }
+
+        Ok(())
+    }
+}
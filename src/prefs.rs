@@ -0,0 +1,122 @@
+use color_eyre::eyre::eyre;
+use structopt::StructOpt;
+
+use crate::{resolve, sqlite};
+
+#[derive(StructOpt)]
+pub enum Args {
+    /// Show the printings and sets chosen in previous runs
+    Show(ShowArgs),
+    /// Forget the choices of previous runs, all of them unless a name or a set is given
+    Clear(ClearArgs),
+}
+
+#[derive(StructOpt)]
+pub struct ShowArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+}
+
+#[derive(StructOpt)]
+pub struct ClearArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Only forget the printing chosen for this name
+    #[structopt(long)]
+    name: Option<String>,
+    /// Only stop preferring the set with this code
+    #[structopt(long)]
+    set: Option<String>,
+}
+
+impl Args {
+    pub fn prefs(self) -> color_eyre::Result<()> {
+        match self {
+            Args::Show(args) => args.show(),
+            Args::Clear(args) => args.clear(),
+        }
+    }
+}
+
+impl ShowArgs {
+    fn show(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        println!("Printings chosen by name:");
+        let mut statement = db.prepare(
+            r#"
+            SELECT
+                preferred_printings.name,
+                scryfall.display_name,
+                upper(scryfall.set_code) || ' ' || scryfall.collector_number
+            FROM preferred_printings LEFT JOIN scryfall ON scryfall.id = preferred_printings.id
+            ORDER BY preferred_printings.name"#,
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            let card: Option<String> = row.get(1)?;
+            let printing: Option<String> = row.get(2)?;
+            match (card, printing) {
+                (Some(card), Some(printing)) => println!("  {}: {} ({})", name, card, printing),
+                _ => println!("  {}: <unknown card>", name),
+            }
+        }
+
+        println!("Preferred sets:");
+        let mut statement = db.prepare(
+            r#"
+            SELECT
+                upper(preferred_sets.set_code),
+                (SELECT set_name FROM scryfall WHERE set_code = preferred_sets.set_code LIMIT 1)
+            FROM preferred_sets
+            ORDER BY preferred_sets.set_code"#,
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let code: String = row.get(0)?;
+            let name: Option<String> = row.get(1)?;
+            match name {
+                Some(name) => println!("  {} ({})", name, code),
+                None => println!("  {}", code),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ClearArgs {
+    fn clear(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        if self.name.is_none() && self.set.is_none() {
+            db.execute("DELETE FROM preferred_printings", [])?;
+            db.execute("DELETE FROM preferred_sets", [])?;
+            return Ok(());
+        }
+
+        if let Some(name) = &self.name {
+            let removed = db.execute(
+                "DELETE FROM preferred_printings WHERE name = ?1",
+                [resolve::pick_key(name)],
+            )?;
+            if removed == 0 {
+                return Err(eyre!("no printing was chosen for {}", name));
+            }
+        }
+        if let Some(set) = &self.set {
+            let removed = db.execute(
+                "DELETE FROM preferred_sets WHERE set_code = lower(?1)",
+                [set],
+            )?;
+            if removed == 0 {
+                return Err(eyre!("{} is not a preferred set", set));
+            }
+        }
+
+        Ok(())
+    }
+}
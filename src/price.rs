@@ -0,0 +1,56 @@
+use color_eyre::eyre::eyre;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Currency {
+    Eur,
+    Usd,
+}
+
+impl Currency {
+    pub const VARIANTS: &'static [&'static str] = &["eur", "usd"];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Currency::Eur => "eur",
+            Currency::Usd => "usd",
+        }
+    }
+
+    /// Column of the `scryfall` table holding the price of a non foil printing
+    pub fn column(self) -> &'static str {
+        match self {
+            Currency::Eur => "eur",
+            Currency::Usd => "usd",
+        }
+    }
+
+    /// SQL expression for the price of a printing of the `scryfall` table, `foil` being a
+    /// boolean SQL expression. Foils without a known foil price use the regular price.
+    pub fn price_expr(self, foil: &str) -> String {
+        format!(
+            "CAST(IIF({foil}, IFNULL(scryfall.{column}_foil, scryfall.{column}), scryfall.{column}) AS REAL)",
+            foil = foil,
+            column = self.column()
+        )
+    }
+
+    pub fn format(self, amount: f64) -> String {
+        match self {
+            Currency::Eur => format!("{:.2}€", amount),
+            Currency::Usd => format!("${:.2}", amount),
+        }
+    }
+}
+
+impl FromStr for Currency {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eur" => Ok(Currency::Eur),
+            "usd" => Ok(Currency::Usd),
+            _ => Err(eyre!("unknown currency {}", s)),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use color_eyre::eyre::eyre;
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+use crate::{collection, price::Currency, sqlite};
+
+#[derive(StructOpt)]
+pub enum Args {
+    /// Record a sealed product, whose cards are then added with `add-list --product`
+    Add(AddArgs),
+    /// Show the products with their expected and pulled value
+    List(ListArgs),
+    /// Show the value pulled from the products of each set, month by month
+    Report(ReportArgs),
+}
+
+#[derive(StructOpt)]
+pub struct AddArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Kind of product (draft, set, collector, ...). Draft boosters have a known content, the
+    /// others need `--slots` or `--ev` to report an expected value.
+    kind: String,
+    /// Code of the set of the product
+    set: String,
+    /// Price paid for the product
+    #[structopt(long)]
+    price: Option<f64>,
+    /// Date the product was opened, as YYYY-MM-DD
+    #[structopt(long, parse(try_from_str = collection::parse_date))]
+    date: Option<String>,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+    /// Expected number of cards of each rarity, as `common=10,uncommon=3,rare=0.875,mythic=0.125`
+    #[structopt(long)]
+    slots: Option<String>,
+    /// Expected value of the product, instead of computing it from the slots
+    #[structopt(long, conflicts_with = "slots")]
+    ev: Option<f64>,
+}
+
+#[derive(StructOpt)]
+pub struct ListArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Only the products of this set
+    #[structopt(long)]
+    set: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct ReportArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+}
+
+const DRAFT_SLOTS: &str = "common=10,uncommon=3,rare=0.875,mythic=0.125";
+
+fn parse_slots(slots: &str) -> color_eyre::Result<Vec<(String, f64)>> {
+    slots
+        .split(',')
+        .map(|slot| {
+            let (rarity, count) = slot
+                .split_once('=')
+                .ok_or_else(|| eyre!("invalid slot {}, expected rarity=count", slot))?;
+            let count = count
+                .trim()
+                .parse()
+                .map_err(|_| eyre!("invalid card count in slot {}", slot))?;
+            Ok((rarity.trim().to_lowercase(), count))
+        })
+        .collect()
+}
+
+/// Price paid for a product and its currency, failing if the product does not exist
+pub fn product_price(db: &Connection, product: i64) -> color_eyre::Result<(Option<f64>, Currency)> {
+    let found: Option<(Option<f64>, String)> = db
+        .query_row(
+            "SELECT price, currency FROM products WHERE product = ?1",
+            [product],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    match found {
+        Some((price, currency)) => Ok((price, currency.parse()?)),
+        None => Err(eyre!("there is no product {}", product)),
+    }
+}
+
+/// Value of a product at the current prices of the cards found in its boosters
+fn expected_value(
+    db: &Connection,
+    set: &str,
+    slots: &str,
+    currency: Currency,
+) -> color_eyre::Result<f64> {
+    let mut average = db.prepare(&format!(
+        r#"
+        SELECT IFNULL(AVG(IFNULL({price}, 0)), 0)
+        FROM scryfall
+        WHERE set_code = ?1 AND rarity = ?2 AND booster AND lang = 'en'"#,
+        price = currency.price_expr("false")
+    ))?;
+    let mut value = 0.;
+    for (rarity, count) in parse_slots(slots)? {
+        let price: f64 = average.query_row(rusqlite::params![set, rarity], |row| row.get(0))?;
+        value += price * count;
+    }
+    Ok(value)
+}
+
+struct Product {
+    id: i64,
+    kind: String,
+    set: String,
+    date: String,
+    price: Option<f64>,
+    currency: Currency,
+    expected: Option<f64>,
+    /// Cards added through the lists of the product, and their current value
+    cards: usize,
+    pulled: f64,
+}
+
+fn products(db: &Connection, set: Option<&str>) -> color_eyre::Result<Vec<Product>> {
+    let mut statement = db.prepare(
+        r#"
+        SELECT product, kind, set_code, date, price, currency, slots, expected
+        FROM products
+        WHERE ?1 IS NULL OR set_code = lower(?1)
+        ORDER BY date, product"#,
+    )?;
+    let mut rows = statement.query([set])?;
+    let mut products = Vec::new();
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let kind: String = row.get(1)?;
+        let set: String = row.get(2)?;
+        let currency: Currency = row.get::<_, String>(5)?.parse()?;
+        let slots: Option<String> = row.get(6)?;
+        let expected = match (row.get(7)?, slots) {
+            (Some(ev), _) => Some(ev),
+            (None, Some(slots)) => Some(expected_value(db, &set, &slots, currency)?),
+            (None, None) if kind == "draft" => {
+                Some(expected_value(db, &set, DRAFT_SLOTS, currency)?)
+            }
+            (None, None) => None,
+        };
+        let (cards, pulled) = db.query_row(
+            &format!(
+                r#"
+                SELECT IFNULL(SUM(list_cards.amount), 0), IFNULL(SUM(list_cards.amount * {price}), 0)
+                FROM
+                    lists
+                    JOIN list_cards ON list_cards.hash = lists.hash
+                    JOIN scryfall ON scryfall.id = list_cards.id
+                WHERE lists.product = ?1"#,
+                price = currency.price_expr("list_cards.foil")
+            ),
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        products.push(Product {
+            id,
+            kind,
+            set,
+            date: row.get(3)?,
+            price: row.get(4)?,
+            currency,
+            expected,
+            cards,
+            pulled,
+        });
+    }
+    Ok(products)
+}
+
+impl Args {
+    pub fn products(self) -> color_eyre::Result<()> {
+        match self {
+            Args::Add(args) => args.add(),
+            Args::List(args) => args.list(),
+            Args::Report(args) => args.report(),
+        }
+    }
+}
+
+impl AddArgs {
+    fn add(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let known: bool = db.query_row(
+            "SELECT COUNT(*) > 0 FROM scryfall WHERE set_code = lower(?1)",
+            [&self.set],
+            |row| row.get(0),
+        )?;
+        if !known {
+            return Err(eyre!("there is no set with the code {}", self.set));
+        }
+        if let Some(slots) = &self.slots {
+            parse_slots(slots)?;
+        }
+
+        db.execute(
+            r#"INSERT INTO products (kind, set_code, price, currency, date, slots, expected)
+            VALUES (?1, lower(?2), ?3, ?4, IFNULL(?5, date('now')), ?6, ?7)"#,
+            rusqlite::params![
+                self.kind,
+                self.set,
+                self.price,
+                self.currency.as_str(),
+                self.date,
+                self.slots,
+                self.ev
+            ],
+        )?;
+        let product = db.last_insert_rowid();
+        println!(
+            "Recorded product {}, add its cards with `add-list --product {}`",
+            product, product
+        );
+
+        Ok(())
+    }
+}
+
+impl ListArgs {
+    fn list(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        for product in products(&db, self.set.as_deref())? {
+            let currency = product.currency;
+            print!(
+                "  {} - {} {} {}",
+                product.id,
+                product.date,
+                product.set.to_uppercase(),
+                product.kind
+            );
+            if let Some(price) = product.price {
+                print!(", paid {}", currency.format(price));
+            }
+            if let Some(expected) = product.expected {
+                print!(", expected {}", currency.format(expected));
+            }
+            match product.cards {
+                0 => println!(", not opened yet"),
+                cards => println!(
+                    ", pulled {} in {} cards",
+                    currency.format(product.pulled),
+                    cards
+                ),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ReportArgs {
+    fn report(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let mut totals: BTreeMap<(String, String, &str), Totals> = BTreeMap::new();
+        for product in products(&db, None)?.into_iter().filter(|p| p.cards > 0) {
+            let key = (
+                product.set.to_uppercase(),
+                product.date[..7].to_string(),
+                product.currency.as_str(),
+            );
+            let entry = totals.entry(key).or_default();
+            entry.opened += 1;
+            entry.paid += product.price.unwrap_or(0.);
+            entry.expected += product.expected.unwrap_or(0.);
+            entry.pulled += product.pulled;
+        }
+
+        let mut set = None;
+        for ((code, month, currency), total) in totals {
+            let currency: Currency = currency.parse()?;
+            if set.as_ref() != Some(&code) {
+                println!("{}:", code);
+                set = Some(code);
+            }
+            println!(
+                "  {}: {} opened, paid {}, expected {}, pulled {}",
+                month,
+                total.opened,
+                currency.format(total.paid),
+                currency.format(total.expected),
+                currency.format(total.pulled)
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    opened: usize,
+    paid: f64,
+    expected: f64,
+    pulled: f64,
+}
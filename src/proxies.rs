@@ -0,0 +1,381 @@
+use color_eyre::eyre::{eyre, WrapErr};
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str};
+use rusqlite::Connection;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+
+use crate::{
+    deck,
+    filter::PrintingFilter,
+    images,
+    price::Currency,
+    resolve::{Line, Resolver},
+    sqlite,
+};
+
+/// Points in a millimeter
+const MM: f32 = 72. / 25.4;
+const CARD_WIDTH: f32 = 63. * MM;
+const CARD_HEIGHT: f32 = 88. * MM;
+const CUT_MARK: f32 = 5. * MM;
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    #[structopt(flatten)]
+    cache: images::Cache,
+    /// Name of a saved deck, or a list in any format accepted by add-list
+    source: String,
+    #[structopt(long, short, default_value = "proxies.pdf")]
+    output: PathBuf,
+    /// Only print the cards the collection is missing for the deck
+    #[structopt(long)]
+    missing: bool,
+    #[structopt(long, default_value = "a4", possible_values = &["a4", "letter"])]
+    paper: String,
+    /// Neither use nor remember the printings chosen in previous runs
+    #[structopt(long)]
+    ignore_prefs: bool,
+    #[structopt(flatten)]
+    printings: PrintingFilter,
+}
+
+/// A card to print, with the cached images of its faces
+struct Proxy {
+    name: String,
+    images: Vec<PathBuf>,
+}
+
+/// Printings of the deck, restricted to the missing copies if asked
+fn deck_cards(
+    db: &Connection,
+    deck: &str,
+    missing: bool,
+) -> color_eyre::Result<Vec<(String, usize)>> {
+    let mut missing: Option<HashMap<String, usize>> = match missing {
+        true => Some(
+            deck::check_deck(db, deck, None, Currency::Eur)?
+                .into_iter()
+                .map(|card| (card.oracle.clone(), card.missing()))
+                .collect(),
+        ),
+        false => None,
+    };
+
+    let mut cards = Vec::new();
+    let mut unknown = Vec::new();
+    let mut statement = db.prepare(
+        r#"
+        SELECT deck_cards.id, scryfall.id IS NULL, IFNULL(scryfall.oracle_id, deck_cards.id), SUM(deck_cards.amount)
+        FROM deck_cards LEFT JOIN scryfall ON scryfall.id = deck_cards.id
+        WHERE deck_cards.deck = ?1
+        GROUP BY deck_cards.id
+        ORDER BY scryfall.display_name"#,
+    )?;
+    let mut rows = statement.query([deck])?;
+    while let Some(row) = rows.next()? {
+        let id: String = row.get(0)?;
+        let is_unknown: bool = row.get(1)?;
+        let oracle: String = row.get(2)?;
+        let mut amount: usize = row.get(3)?;
+        if let Some(missing) = &mut missing {
+            let left = missing.entry(oracle).or_default();
+            amount = amount.min(*left);
+            *left -= amount;
+        }
+        match amount {
+            0 => (),
+            _ if is_unknown => unknown.push(id),
+            _ => cards.push((id, amount)),
+        }
+    }
+    if !unknown.is_empty() {
+        println!(
+            "{} cards are missing from the card database and are not printed:",
+            unknown.len()
+        );
+        for id in unknown {
+            println!("  unknown card {}", id);
+        }
+    }
+    Ok(cards)
+}
+
+fn list_cards(
+    db: &Connection,
+    list: &Path,
+    printings: &PrintingFilter,
+    ignore_prefs: bool,
+) -> color_eyre::Result<Vec<(String, usize)>> {
+    let list = std::fs::read_to_string(list)
+        .wrap_err_with(|| format!("{} is neither a saved deck nor a list", list.display()))?;
+
+    let mut resolver = Resolver::with_prefs(db, printings, ignore_prefs)?;
+    let mut cards = Vec::new();
+    for line in list.lines() {
+        let entry = match resolver.parse_line(line)? {
+            Line::Card(entry) => entry,
+            Line::Section(_) | Line::Empty => continue,
+        };
+        cards.push((resolver.resolve(&entry.card)?, entry.count));
+    }
+    resolver.save_prefs()?;
+    Ok(cards)
+}
+
+/// Cached images of the whole card, or of each of its faces for double faced cards
+fn proxy(db: &Connection, cache: &images::Cache, id: &str) -> color_eyre::Result<Proxy> {
+    let name = db.query_row(
+        "SELECT display_name FROM scryfall WHERE id = ?1",
+        [id],
+        |row| row.get(0),
+    )?;
+
+    let images = match cache.lookup(db, id, 0)? {
+        Some(image) => vec![image],
+        None => {
+            let faces: u32 = db.query_row(
+                "SELECT COUNT(*) FROM scryfall_faces WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )?;
+            (1..=faces)
+                .map(|face| cache.lookup(db, id, face))
+                .filter_map(Result::transpose)
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    Ok(Proxy { name, images })
+}
+
+/// Holds the pdf being written and the ids of its objects
+struct Sheets {
+    pdf: Pdf,
+    next: i32,
+    pages: Vec<Ref>,
+    /// Images already embedded, by path
+    images: HashMap<PathBuf, (Ref, String)>,
+}
+
+impl Sheets {
+    const CATALOG: Ref = Ref::new(1);
+    const PAGE_TREE: Ref = Ref::new(2);
+    const FONT: Ref = Ref::new(3);
+
+    fn new() -> Self {
+        let mut pdf = Pdf::new();
+        pdf.type1_font(Self::FONT).base_font(Name(b"Helvetica"));
+        Self {
+            pdf,
+            next: 4,
+            pages: Vec::new(),
+            images: HashMap::new(),
+        }
+    }
+
+    fn alloc(&mut self) -> Ref {
+        self.next += 1;
+        Ref::new(self.next - 1)
+    }
+
+    fn embed(&mut self, path: &Path) -> color_eyre::Result<(Ref, String)> {
+        if let Some(image) = self.images.get(path) {
+            return Ok(image.clone());
+        }
+
+        let data =
+            std::fs::read(path).wrap_err_with(|| format!("could not read {}", path.display()))?;
+        if !matches!(imagesize::image_type(&data), Ok(imagesize::ImageType::Jpeg)) {
+            return Err(eyre!(
+                "{} is not a jpeg image, use images of another --kind",
+                path.display()
+            ));
+        }
+        let size = imagesize::blob_size(&data)
+            .map_err(|e| eyre!("could not read the size of {}: {:?}", path.display(), e))?;
+
+        let id = self.alloc();
+        let mut image = self.pdf.image_xobject(id, &data);
+        image.filter(Filter::DctDecode);
+        image.width(size.width as i32);
+        image.height(size.height as i32);
+        image.color_space().device_rgb();
+        image.bits_per_component(8);
+        image.finish();
+
+        let image = (id, format!("Im{}", self.images.len()));
+        self.images.insert(path.to_owned(), image.clone());
+        Ok(image)
+    }
+
+    /// Lays out up to nine card images on a page, the ones without an image being replaced by
+    /// their name in a frame
+    fn page(
+        &mut self,
+        paper: (f32, f32),
+        slots: &[(String, Option<PathBuf>)],
+    ) -> color_eyre::Result<()> {
+        let (width, height) = paper;
+        let left = (width - 3. * CARD_WIDTH) / 2.;
+        let bottom = (height - 3. * CARD_HEIGHT) / 2.;
+
+        let mut content = Content::new();
+        let mut images = Vec::new();
+        for (slot, (name, image)) in slots.iter().enumerate() {
+            let x = left + (slot % 3) as f32 * CARD_WIDTH;
+            let y = bottom + (2 - slot / 3) as f32 * CARD_HEIGHT;
+            match image {
+                Some(path) => {
+                    let (id, image_name) = self.embed(path)?;
+                    content.save_state();
+                    content.transform([CARD_WIDTH, 0., 0., CARD_HEIGHT, x, y]);
+                    content.x_object(Name(image_name.as_bytes()));
+                    content.restore_state();
+                    if !images.contains(&(id, image_name.clone())) {
+                        images.push((id, image_name));
+                    }
+                }
+                None => {
+                    content.set_stroke_gray(0.5);
+                    content.rect(x, y, CARD_WIDTH, CARD_HEIGHT).stroke();
+                    content.begin_text();
+                    content.set_font(Name(b"F1"), 10.);
+                    content.next_line(x + 4. * MM, y + CARD_HEIGHT - 8. * MM);
+                    content.show(Str(deunicode::deunicode(name).as_bytes()));
+                    content.end_text();
+                }
+            }
+        }
+
+        content.set_stroke_gray(0.);
+        content.set_line_width(0.5);
+        for column in 0..=3 {
+            let x = left + column as f32 * CARD_WIDTH;
+            content
+                .move_to(x, bottom - CUT_MARK)
+                .line_to(x, bottom - 1.);
+            let top = bottom + 3. * CARD_HEIGHT;
+            content.move_to(x, top + 1.).line_to(x, top + CUT_MARK);
+        }
+        for row in 0..=3 {
+            let y = bottom + row as f32 * CARD_HEIGHT;
+            content.move_to(left - CUT_MARK, y).line_to(left - 1., y);
+            let right = left + 3. * CARD_WIDTH;
+            content.move_to(right + 1., y).line_to(right + CUT_MARK, y);
+        }
+        content.stroke();
+
+        let page_id = self.alloc();
+        let content_id = self.alloc();
+        self.pdf.stream(content_id, &content.finish());
+
+        let mut page = self.pdf.page(page_id);
+        page.media_box(Rect::new(0., 0., width, height));
+        page.parent(Self::PAGE_TREE);
+        page.contents(content_id);
+        let mut resources = page.resources();
+        resources.fonts().pair(Name(b"F1"), Self::FONT);
+        let mut objects = resources.x_objects();
+        for (id, name) in &images {
+            objects.pair(Name(name.as_bytes()), *id);
+        }
+        objects.finish();
+        resources.finish();
+        page.finish();
+
+        self.pages.push(page_id);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.pdf.catalog(Self::CATALOG).pages(Self::PAGE_TREE);
+        let count = self.pages.len() as i32;
+        self.pdf
+            .pages(Self::PAGE_TREE)
+            .kids(self.pages.iter().copied())
+            .count(count);
+        self.pdf.finish()
+    }
+}
+
+impl Args {
+    pub fn proxies(self) -> color_eyre::Result<()> {
+        let db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let cards = match deck::deck_exists(&db, &self.source) {
+            Ok(()) => deck_cards(&db, &self.source, self.missing)?,
+            Err(_) if self.missing => {
+                return Err(eyre!(
+                    "--missing needs a saved deck, {} is not one",
+                    self.source
+                ))
+            }
+            Err(_) => list_cards(
+                &db,
+                Path::new(&self.source),
+                &self.printings,
+                self.ignore_prefs,
+            )?,
+        };
+
+        let mut slots = Vec::new();
+        let mut without_image = Vec::new();
+        for (id, amount) in cards {
+            let proxy = proxy(&db, &self.cache, &id)?;
+            for _ in 0..amount {
+                match proxy.images.is_empty() {
+                    true => slots.push((proxy.name.clone(), None)),
+                    false => slots.extend(
+                        proxy
+                            .images
+                            .iter()
+                            .map(|image| (proxy.name.clone(), Some(image.clone()))),
+                    ),
+                }
+            }
+            if proxy.images.is_empty() {
+                without_image.push(proxy.name);
+            }
+        }
+        if slots.is_empty() {
+            println!("No card to print");
+            return Ok(());
+        }
+
+        let paper = match self.paper.as_str() {
+            "letter" => (612., 792.),
+            _ => (210. * MM, 297. * MM),
+        };
+        let mut sheets = Sheets::new();
+        for page in slots.chunks(9) {
+            sheets.page(paper, page)?;
+        }
+        let pages = sheets.pages.len();
+        std::fs::write(&self.output, sheets.finish())
+            .wrap_err_with(|| format!("could not write {}", self.output.display()))?;
+
+        println!(
+            "Wrote {} proxies on {} pages to {}",
+            slots.len(),
+            pages,
+            self.output.display()
+        );
+        if !without_image.is_empty() {
+            println!(
+                "{} cards have no cached image and are printed as their name, run `images fetch` first:",
+                without_image.len()
+            );
+            for name in without_image {
+                println!("  {}", name);
+            }
+        }
+
+        Ok(())
+    }
+}
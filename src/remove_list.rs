@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use crate::{
+    collection::{self, Deal},
+    filter::PrintingFilter,
+    price::Currency,
+    resolve::{Line, Resolver},
+    sqlite,
+};
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    /// Cards to remove, written like in an add-list file
+    list: PathBuf,
+    /// Total received for the cards without a price of their own, shared according to their
+    /// current price. Without any price, cards sold to a buyer are counted at their current
+    /// price and the other ones are recorded as removed without a sale.
+    #[structopt(long)]
+    price: Option<f64>,
+    /// Date the cards left the collection, as YYYY-MM-DD
+    #[structopt(long, parse(try_from_str = collection::parse_date))]
+    date: Option<String>,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+    /// Who the cards were sold to
+    #[structopt(long)]
+    buyer: Option<String>,
+    /// Why the cards left the collection, when they were not sold
+    #[structopt(long, conflicts_with = "buyer")]
+    reason: Option<String>,
+    /// Neither use nor remember the printings chosen in previous runs
+    #[structopt(long)]
+    ignore_prefs: bool,
+    /// Whose collection the cards are removed from
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    owner: Option<String>,
+}
+
+impl Args {
+    pub fn remove_list(self) -> color_eyre::Result<()> {
+        let card_list = std::fs::read_to_string(&self.list)?;
+
+        let mut db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let tx = db.transaction()?;
+        let mut removed = 0;
+        {
+            let mut resolver =
+                Resolver::with_prefs(&tx, &PrintingFilter::default(), self.ignore_prefs)?;
+            let mut cards = Vec::new();
+            for line in card_list.lines() {
+                let entry = match resolver.parse_line(line)? {
+                    Line::Card(entry) => entry,
+                    Line::Section(_) | Line::Empty => continue,
+                };
+                let id = resolver.resolve(&entry.card)?;
+                cards.push((id, entry.foil, entry.count, entry.price, entry.date));
+            }
+            resolver.save_prefs()?;
+
+            let unpriced: Vec<_> = cards
+                .iter()
+                .filter(|card| card.3.is_none())
+                .map(|(id, foil, count, _, _)| (id.as_str(), *foil, *count))
+                .collect();
+            let kind = match self.buyer {
+                Some(_) => "sale",
+                None => "removal",
+            };
+            let exchange = collection::record_exchange(
+                &tx,
+                kind,
+                self.buyer.as_deref(),
+                self.reason.as_deref(),
+                None,
+                Deal {
+                    currency: self.currency,
+                    date: self.date.as_deref(),
+                    ..Deal::unknown()
+                },
+            )?;
+
+            let mut shared = match self.price {
+                Some(total) if !unpriced.is_empty() => {
+                    collection::share_price(&tx, total, self.currency, &unpriced)?
+                }
+                _ => Vec::new(),
+            }
+            .into_iter();
+
+            for (id, foil, count, price, date) in &cards {
+                let mut price = price.or_else(|| shared.next());
+                // Sales without a price are made at the current one
+                if price.is_none() && self.buyer.is_some() {
+                    price = collection::market_price(&tx, id, *foil, self.currency)?;
+                }
+                let deal = Deal {
+                    price,
+                    currency: self.currency,
+                    date: date.or(self.date.as_deref()),
+                    exchange: Some(exchange),
+                };
+                collection::remove_cards(
+                    &tx,
+                    id,
+                    *foil,
+                    *count,
+                    self.owner.as_deref().unwrap_or_default(),
+                    deal,
+                )?;
+                removed += count;
+            }
+        }
+        tx.commit()?;
+
+        println!("Removed {} cards", removed);
+        Ok(())
+    }
+}
@@ -0,0 +1,928 @@
+use color_eyre::eyre::eyre;
+use rusqlite::{Connection, OptionalExtension, Statement};
+use serde::Serialize;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use crate::{collection, filter::PrintingFilter, picker};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CardInfo {
+    pub promo: bool,
+    pub set: String,
+    pub set_code: String,
+    pub oracle: String,
+    pub name: String,
+    pub id: String,
+    pub uri: String,
+    pub score: usize,
+    pub collector_number: String,
+    pub rarity: String,
+    pub mana_cost: Option<String>,
+    pub eur: Option<f64>,
+    pub usd: Option<f64>,
+    pub layout: String,
+    /// Face whose name matched, when only one face of the card was given
+    pub face: Option<String>,
+}
+
+#[derive(Default, Clone)]
+pub struct SortingCtx {
+    pub chosen: HashSet<String>,
+    /// Codes of the sets chosen before, listed first
+    pub chosen_set: HashSet<String>,
+    /// Printing chosen for each name, used without asking again
+    pub picks: HashMap<String, String>,
+}
+
+impl SortingCtx {
+    /// Loads the choices remembered from the previous runs
+    pub fn load(db: &Connection) -> rusqlite::Result<Self> {
+        let picks: HashMap<String, String> = db
+            .prepare("SELECT name, id FROM preferred_printings")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        let chosen_set = db
+            .prepare("SELECT set_code FROM preferred_sets")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(SortingCtx {
+            chosen: picks.values().cloned().collect(),
+            chosen_set,
+            picks,
+        })
+    }
+
+    /// Remembers the choices for the next runs, replacing the stored ones
+    pub fn save(&self, db: &Connection) -> rusqlite::Result<()> {
+        db.execute("DELETE FROM preferred_printings", [])?;
+        for (name, id) in &self.picks {
+            db.execute(
+                "INSERT INTO preferred_printings (name, id) VALUES (?1, ?2)",
+                [name, id],
+            )?;
+        }
+        db.execute("DELETE FROM preferred_sets", [])?;
+        for set in &self.chosen_set {
+            db.execute("INSERT INTO preferred_sets (set_code) VALUES (?1)", [set])?;
+        }
+        Ok(())
+    }
+}
+
+/// Folds a name the way the names of the `scryfall` table are stored: in lowercase ASCII, so
+/// that names can be typed without their accents or in any script
+pub fn fold_name(name: &str) -> String {
+    let mut name = deunicode::deunicode(name.trim());
+    name.make_ascii_lowercase();
+    name
+}
+
+/// Key of a name in the remembered picks
+pub fn pick_key(name: &str) -> String {
+    fold_name(name)
+}
+
+/// Answer of the user when asked to choose between several cards
+pub enum Choice<T> {
+    Chosen(T),
+    /// Leave the card out
+    Skip,
+    /// Go back to the previous choice
+    Undo,
+}
+
+/// Asks the user to choose between `cards`, using the full screen picker when running in a
+/// terminal. `undo` tells if there is a previous choice to go back to.
+pub fn choose_correct_card(
+    choice: &str,
+    mut cards: Vec<CardInfo>,
+    sorting_ctx: &mut SortingCtx,
+    undo: bool,
+) -> color_eyre::Result<Choice<CardInfo>> {
+    fn sort_cards(a: &CardInfo, b: &CardInfo) -> Ordering {
+        a.score
+            .cmp(&b.score)
+            .then(a.name.cmp(&b.name).then(a.set.cmp(&b.set)))
+    }
+
+    fn sort_on_set(a: &CardInfo, b: &CardInfo, sorting_ctx: &SortingCtx) -> Ordering {
+        match (
+            sorting_ctx.chosen_set.contains(&a.set_code),
+            sorting_ctx.chosen_set.contains(&b.set_code),
+        ) {
+            (true, true) | (false, false) => sort_cards(a, b),
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+        }
+    }
+
+    fn sort_on_id(a: &CardInfo, b: &CardInfo, sorting_ctx: &SortingCtx) -> Ordering {
+        match (
+            sorting_ctx.chosen.contains(&a.id),
+            sorting_ctx.chosen.contains(&b.id),
+        ) {
+            (true, true) | (false, false) => sort_on_set(a, b, sorting_ctx),
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+        }
+    }
+
+    if cards.is_empty() {
+        return Err(eyre!("no card matches {}", choice));
+    }
+
+    cards.sort_unstable_by(|a, b| sort_on_id(a, b, sorting_ctx));
+
+    let choice = match picker::is_available() {
+        true => picker::pick(choice, &cards, undo)?,
+        false => prompt_card(choice, &cards, undo)?,
+    };
+    Ok(match choice {
+        Choice::Chosen(idx) => {
+            let value = cards.swap_remove(idx);
+            sorting_ctx.chosen.insert(value.id.clone());
+            sorting_ctx.chosen_set.insert(value.set_code.clone());
+            Choice::Chosen(value)
+        }
+        Choice::Skip => Choice::Skip,
+        Choice::Undo => Choice::Undo,
+    })
+}
+
+/// Line based chooser, used when not running in a terminal
+fn prompt_card(choice: &str, cards: &[CardInfo], undo: bool) -> color_eyre::Result<Choice<usize>> {
+    println!("Choose match for {}:", choice);
+    let mut iterator = cards.iter().enumerate();
+    let mut remaining = cards.len();
+    let mut show = true;
+    loop {
+        if show {
+            for (idx, card) in iterator.by_ref().take(10) {
+                println!(
+                    "  - [{}] {}{} - {}{} [{}]({})",
+                    idx + 1,
+                    card.name,
+                    card.face
+                        .as_ref()
+                        .map(|f| format!(" (face {})", f))
+                        .unwrap_or_default(),
+                    card.set,
+                    if card.promo { " (promo)" } else { "" },
+                    card.score,
+                    card.uri
+                );
+                remaining -= 1;
+            }
+            show = false;
+        }
+        let text = format!(
+            "Correct card ({}s to skip{})?",
+            if remaining == 0 {
+                ""
+            } else {
+                "0 for more choices, "
+            },
+            if undo {
+                ", u to undo the previous one"
+            } else {
+                ""
+            }
+        );
+        let value: String = promptly::prompt(text)?;
+        match value.trim() {
+            "s" => return Ok(Choice::Skip),
+            "u" if undo => return Ok(Choice::Undo),
+            "0" => show = true,
+            value => match value.parse::<usize>() {
+                Ok(value) if value >= 1 && value <= cards.len() => {
+                    return Ok(Choice::Chosen(value - 1))
+                }
+                _ => continue,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    Main,
+    Side,
+    Commander,
+}
+
+impl Board {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Board::Main => "main",
+            Board::Side => "side",
+            Board::Commander => "commander",
+        }
+    }
+}
+
+impl FromStr for Board {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "main" => Ok(Board::Main),
+            "side" => Ok(Board::Side),
+            "commander" => Ok(Board::Commander),
+            _ => Err(eyre!("unknown board {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CardRef<'a> {
+    Id(&'a str),
+    Name {
+        name: &'a str,
+        set: Option<&'a str>,
+        number: Option<&'a str>,
+    },
+}
+
+impl<'a> CardRef<'a> {
+    pub fn name(&self) -> &'a str {
+        match *self {
+            CardRef::Id(id) => id,
+            CardRef::Name { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Entry<'a> {
+    pub count: usize,
+    pub foil: bool,
+    pub card: CardRef<'a>,
+    /// Price of each copy, written after an `@`
+    pub price: Option<f64>,
+    pub date: Option<&'a str>,
+    pub tags: Vec<&'a str>,
+    /// The card named by the whole line, when its leading number may belong to the name
+    /// (`1996 World Champion`)
+    pub uncounted: Option<CardRef<'a>>,
+}
+
+#[derive(Debug)]
+pub enum Line<'a> {
+    Empty,
+    Section(Board),
+    Card(Entry<'a>),
+}
+
+/// Splits an arena style `Name (SET) 123` suffix from a card name
+fn split_printing(name: &str) -> (&str, Option<&str>, Option<&str>) {
+    if let Some(open) = name.rfind(" (") {
+        let after = &name[open + 2..];
+        if let Some(close) = after.find(')') {
+            let set = &after[..close];
+            let number = after[close + 1..].trim();
+            if !set.is_empty() && !set.contains(' ') && !number.contains(' ') {
+                let number = Some(number).filter(|n| !n.is_empty());
+                return (name[..open].trim(), Some(set), number);
+            }
+        }
+    }
+    (name, None, None)
+}
+
+/// Parses a line of a card list.
+///
+/// A card line is an optional count (`4` or `4x`), followed by markers (`[F]` for foils, `[id]`
+/// when the rest of the line is a scryfall id, `[#tag]` to tag the copies) and the card name, optionally followed by the
+/// printing as `(SET) number`, and by the price of each copy with an optional date
+/// (`@ 1.50 2021-06-01`). Lines naming a section (`Deck`, `Sideboard`, `Commander`) change the
+/// board the following cards belong to.
+pub fn parse_line(line: &str) -> color_eyre::Result<Line<'_>> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(Line::Empty);
+    }
+
+    match line.trim_end_matches(':').to_ascii_lowercase().as_str() {
+        "deck" | "main" | "mainboard" => return Ok(Line::Section(Board::Main)),
+        "sideboard" => return Ok(Line::Section(Board::Side)),
+        "commander" => return Ok(Line::Section(Board::Commander)),
+        _ => (),
+    }
+
+    let mut rest = line;
+    let mut count = 1;
+    let mut counted = false;
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+    if digits > 0 {
+        let after = rest[digits..].strip_prefix('x').unwrap_or(&rest[digits..]);
+        if let (true, Ok(c)) = (
+            after.starts_with(char::is_whitespace),
+            rest[..digits].parse(),
+        ) {
+            if c == 0 {
+                return Err(eyre!("{} has no copies", line));
+            }
+            count = c;
+            counted = true;
+            rest = after.trim_start();
+        }
+    }
+
+    let mut entry = parse_entry(line, rest)?;
+    entry.count = count;
+    // Markers come after the count, a number followed by one is never part of a name
+    let named = matches!(entry.card, CardRef::Name { .. });
+    if counted && named && !entry.foil && entry.tags.is_empty() {
+        entry.uncounted = Some(parse_entry(line, line)?.card);
+    }
+    Ok(Line::Card(entry))
+}
+
+/// Parses the part of a card line following its count
+fn parse_entry<'a>(line: &str, mut rest: &'a str) -> color_eyre::Result<Entry<'a>> {
+    let mut foil = false;
+    let mut is_id = false;
+    let mut tags = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(r) = rest.strip_prefix("[F]") {
+            foil = true;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("[id]") {
+            is_id = true;
+            rest = r;
+        } else if let (Some(r), Some(end)) = (rest.strip_prefix("[#"), rest.find(']')) {
+            let tag = r[..end - 2].trim();
+            if tag.is_empty() {
+                return Err(eyre!("{} has an empty tag", line));
+            }
+            tags.push(tag);
+            rest = &rest[end + 1..];
+        } else {
+            break;
+        }
+    }
+
+    let mut price = None;
+    let mut date = None;
+    if let Some(at) = rest.rfind('@') {
+        let mut deal = rest[at + 1..].split_whitespace();
+        let parsed = deal.next().map(str::parse::<f64>);
+        let when = deal.next();
+        if let (Some(Ok(p)), true, None) = (
+            parsed,
+            when.map(collection::is_date).unwrap_or(true),
+            deal.next(),
+        ) {
+            price = Some(p);
+            date = when;
+            rest = &rest[..at];
+        }
+    }
+
+    let card = if is_id {
+        CardRef::Id(rest.trim())
+    } else {
+        let (name, set, number) = split_printing(rest.trim());
+        CardRef::Name { name, set, number }
+    };
+
+    Ok(Entry {
+        count: 1,
+        foil,
+        card,
+        price,
+        date,
+        tags,
+        uncounted: None,
+    })
+}
+
+fn parse_row(row: &rusqlite::Row) -> rusqlite::Result<CardInfo> {
+    Ok(CardInfo {
+        name: row.get(0)?,
+        id: row.get(1)?,
+        uri: row.get(2)?,
+        set: row.get(3)?,
+        promo: row.get(4)?,
+        score: row.get(5)?,
+        set_code: row.get(6)?,
+        oracle: row.get(7)?,
+        collector_number: row.get(8)?,
+        rarity: row.get(9)?,
+        mana_cost: row.get(10)?,
+        eur: row.get(11)?,
+        usd: row.get(12)?,
+        layout: row.get(13)?,
+        face: row.get(14)?,
+    })
+}
+
+pub enum Resolution {
+    Found(String),
+    Ambiguous(Vec<CardInfo>),
+}
+
+enum Lookup {
+    Printing { id: String, oracle: String },
+    Candidates { names: Vec<CardInfo>, exact: bool },
+}
+
+/// Columns of the `scryfall` table shown to the user when choosing a card
+const DETAILS: &str = r#"
+    scryfall.collector_number,
+    scryfall.rarity,
+    (SELECT cost FROM scryfall_mana WHERE scryfall_mana.id = scryfall.id ORDER BY face LIMIT 1),
+    CAST(scryfall.eur AS REAL),
+    CAST(scryfall.usd AS REAL),
+    scryfall.layout"#;
+
+/// Resolves card names to scryfall ids, asking the user when there is an ambiguity
+pub struct Resolver<'conn> {
+    direct_match: Statement<'conn>,
+    match_error: Statement<'conn>,
+    duo: Statement<'conn>,
+    face_match: Statement<'conn>,
+    face_error: Statement<'conn>,
+    printing: Statement<'conn>,
+    oracle: Statement<'conn>,
+    in_lang: Statement<'conn>,
+    /// Language of the printings to choose from, English being the fallback
+    pub lang: Option<String>,
+    pub sorting_ctx: SortingCtx,
+    db: &'conn Connection,
+    /// Store the choices of the user in the database
+    remember: bool,
+}
+
+impl<'conn> Resolver<'conn> {
+    /// The connection must have the spellfix extension loaded. Only the printings selected by
+    /// `printings` are offered.
+    pub fn new(db: &'conn Connection, printings: &PrintingFilter) -> rusqlite::Result<Self> {
+        let printings = printings.condition();
+        let direct_match = db.prepare(&format!(
+            r#"
+            SELECT
+                printed_name,
+                id,
+                uri,
+                set_name,
+                promo,
+                0 as score,
+                set_code,
+                IFNULL(oracle_id, id),
+                {details},
+                NULL
+            FROM
+                scryfall
+            WHERE
+                printed_name = ?1
+                AND {printings}
+            UNION
+            SELECT
+                name,
+                id,
+                uri,
+                set_name,
+                promo,
+                0 as score,
+                set_code,
+                IFNULL(oracle_id, id),
+                {details},
+                NULL
+            FROM
+                scryfall
+            WHERE name = ?1
+                AND printed_name IS NULL
+                AND {printings};
+        "#,
+            details = DETAILS,
+            printings = printings
+        ))?;
+
+        let match_error = db.prepare(&format!(
+            r#"
+            SELECT
+                IIF(printed_name IS NULL, name, printed_name) as nm,
+                id,
+                uri,
+                set_name,
+                promo,
+                score,
+                set_code,
+                IFNULL(oracle_id, id),
+                {details},
+                NULL
+            FROM
+                scryfall,
+                card_names
+            WHERE
+                word MATCH ?1
+                AND (
+                    printed_name = word
+                    OR (
+                        name = word
+                        AND printed_name IS NULL
+                    )
+                )
+                AND {printings};
+            "#,
+            details = DETAILS,
+            printings = printings
+        ))?;
+
+        let duo = db.prepare(&format!(
+            r#"
+            SELECT
+                search.name,search.id,uri,set_name,promo,search.score,set_code,
+                IFNULL(scryfall.oracle_id, scryfall.id),
+                {details},
+                NULL
+            FROM scryfall,
+            (
+                SELECT
+                    fn1.id as id,
+                    (f1.score + f2.score)/2 as score,
+                    fn1.name || ' // ' || fn2.name as name
+                FROM
+                    face_names as f1,
+                    scryfall_faces as fn1,
+                    scryfall_faces as fn2,
+                    face_names as f2
+                WHERE
+                    f1.word MATCH ?1
+                    AND fn1.name = f1.word
+                    AND f2.word MATCH ?2
+                    AND fn2.name = f2.word
+                    AND fn2.name != fn1.name
+                    AND fn1.id = fn2.id
+                ORDER BY score
+            ) as search
+            WHERE
+                search.id = scryfall.id
+                AND {printings};"#,
+            details = DETAILS,
+            printings = printings
+        ))?;
+
+        let face_match = db.prepare(&format!(
+            r#"
+            SELECT DISTINCT
+                IIF(scryfall.printed_name IS NULL, scryfall.name, scryfall.printed_name),
+                scryfall.id,
+                uri,
+                set_name,
+                promo,
+                0 as score,
+                set_code,
+                IFNULL(oracle_id, scryfall.id),
+                {details},
+                scryfall_faces.display_name
+            FROM scryfall_faces JOIN scryfall ON scryfall.id = scryfall_faces.id
+            WHERE scryfall_faces.name = ?1 AND {printings}"#,
+            details = DETAILS,
+            printings = printings
+        ))?;
+
+        let face_error = db.prepare(&format!(
+            r#"
+            SELECT DISTINCT
+                IIF(scryfall.printed_name IS NULL, scryfall.name, scryfall.printed_name),
+                scryfall.id,
+                uri,
+                set_name,
+                promo,
+                score,
+                set_code,
+                IFNULL(oracle_id, scryfall.id),
+                {details},
+                scryfall_faces.display_name
+            FROM
+                scryfall,
+                scryfall_faces,
+                face_names
+            WHERE
+                word MATCH ?1
+                AND scryfall_faces.name = word
+                AND scryfall.id = scryfall_faces.id
+                AND {printings};
+            "#,
+            details = DETAILS,
+            printings = printings
+        ))?;
+
+        let oracle = db.prepare("SELECT IFNULL(oracle_id, id) FROM scryfall WHERE id = ?1")?;
+
+        let printing = db.prepare(&format!(
+            r#"
+            SELECT id, IFNULL(oracle_id, id) FROM scryfall
+            WHERE set_code = lower(?1) AND collector_number = ?2 AND {}
+            ORDER BY lang = ?3 DESC, lang = 'en' DESC
+            LIMIT 1"#,
+            printings
+        ))?;
+
+        let in_lang = db.prepare(&format!(
+            r#"
+            SELECT
+                IIF(printed_name IS NULL, name, printed_name),
+                id,
+                uri,
+                set_name,
+                promo,
+                ?3 as score,
+                set_code,
+                IFNULL(oracle_id, id),
+                {details},
+                NULL
+            FROM scryfall
+            WHERE (oracle_id = ?1 OR id = ?1) AND lang = ?2 AND {printings}"#,
+            details = DETAILS,
+            printings = printings
+        ))?;
+
+        Ok(Resolver {
+            direct_match,
+            match_error,
+            duo,
+            face_match,
+            face_error,
+            printing,
+            oracle,
+            in_lang,
+            lang: None,
+            sorting_ctx: SortingCtx::default(),
+            db,
+            remember: false,
+        })
+    }
+
+    /// Creates a resolver using the choices of the previous runs, and remembering the new ones
+    /// when `save_prefs` is called. With `ignore_prefs` it behaves like `new`.
+    pub fn with_prefs(
+        db: &'conn Connection,
+        printings: &PrintingFilter,
+        ignore_prefs: bool,
+    ) -> rusqlite::Result<Self> {
+        let mut resolver = Self::new(db, printings)?;
+        if !ignore_prefs {
+            resolver.sorting_ctx = SortingCtx::load(db)?;
+            resolver.remember = true;
+        }
+        Ok(resolver)
+    }
+
+    pub fn save_prefs(&self) -> rusqlite::Result<()> {
+        match self.remember {
+            true => self.sorting_ctx.save(self.db),
+            false => Ok(()),
+        }
+    }
+
+    /// Returns the printings matching `name`, and whether they are exact matches or only
+    /// spelling corrections
+    pub fn candidates(&mut self, name: &str) -> rusqlite::Result<(Vec<CardInfo>, bool)> {
+        let name = fold_name(name);
+
+        let names: Vec<CardInfo> = if let Some(p) = name.find("//") {
+            let (first, second) = name.split_at(p);
+            let first = first.trim();
+            let second = second[2..].trim();
+            self.duo
+                .query_map([first, second], parse_row)?
+                .collect::<Result<_, _>>()?
+        } else {
+            self.direct_match
+                .query_map([&name], parse_row)?
+                .collect::<Result<_, _>>()?
+        };
+
+        if !names.is_empty() {
+            return Ok((names, true));
+        }
+
+        // Only one face of a split, adventure, flip or double faced card may be given
+        let faces: Vec<CardInfo> = self
+            .face_match
+            .query_map([&name], parse_row)?
+            .collect::<Result<_, _>>()?;
+        if !faces.is_empty() {
+            return Ok((faces, true));
+        }
+
+        let mut corrections: Vec<CardInfo> = self
+            .match_error
+            .query_map([&name], parse_row)?
+            .collect::<Result<_, _>>()?;
+        for correction in self.face_error.query_map([&name], parse_row)? {
+            corrections.push(correction?);
+        }
+        Ok((corrections, false))
+    }
+
+    /// Replaces the candidates by the printings of the same cards in the chosen language, or in
+    /// English for the cards never printed in it
+    fn in_lang(&mut self, names: Vec<CardInfo>) -> rusqlite::Result<Vec<CardInfo>> {
+        let lang = self.lang.as_deref().unwrap_or("en");
+        let mut oracles: Vec<(String, usize)> = Vec::new();
+        for card in names {
+            match oracles
+                .iter_mut()
+                .find(|(oracle, _)| *oracle == card.oracle)
+            {
+                Some((_, score)) => *score = (*score).min(card.score),
+                None => oracles.push((card.oracle, card.score)),
+            }
+        }
+
+        let mut printings = Vec::new();
+        for (oracle, score) in oracles {
+            let mut found: Vec<CardInfo> = self
+                .in_lang
+                .query_map(rusqlite::params![oracle, lang, score], parse_row)?
+                .collect::<Result<_, _>>()?;
+            if found.is_empty() {
+                found = self
+                    .in_lang
+                    .query_map(rusqlite::params![oracle, "en", score], parse_row)?
+                    .collect::<Result<_, _>>()?;
+            }
+            printings.extend(found);
+        }
+        Ok(printings)
+    }
+
+    /// Finds the printing given by an arena style `(SET) number`, or the candidates for a name
+    /// restricted to the set if one was given
+    fn lookup(
+        &mut self,
+        name: &str,
+        set: Option<&str>,
+        number: Option<&str>,
+    ) -> rusqlite::Result<Lookup> {
+        if let (Some(set), Some(number)) = (set, number) {
+            let printing = self
+                .printing
+                .query_row(rusqlite::params![set, number, self.lang], |row| {
+                    Ok(Lookup::Printing {
+                        id: row.get(0)?,
+                        oracle: row.get(1)?,
+                    })
+                })
+                .optional()?;
+            if let Some(printing) = printing {
+                return Ok(printing);
+            }
+        }
+
+        if name.contains("//") {
+            println!("Handling double card {}", name);
+        }
+        let (mut names, exact) = self.candidates(name)?;
+        if let Some(card) = names.first().filter(|c| exact && c.face.is_some()) {
+            println!("{} is a face of {}", name, card.name);
+        }
+        if self.lang.is_some() {
+            names = self.in_lang(names)?;
+        }
+
+        if let Some(set) = set {
+            let set = set.to_lowercase();
+            if names.iter().any(|c| c.set_code == set) {
+                names.retain(|c| c.set_code == set);
+            }
+        }
+
+        Ok(Lookup::Candidates { names, exact })
+    }
+
+    /// Finds the scryfall id of a card without asking the user, returning the candidates if
+    /// there is an ambiguity
+    pub fn try_resolve(&mut self, card: &CardRef) -> rusqlite::Result<Resolution> {
+        let (name, set, number) = match *card {
+            CardRef::Id(id) => return Ok(Resolution::Found(id.to_string())),
+            CardRef::Name { name, set, number } => (name, set, number),
+        };
+
+        match self.lookup(name, set, number)? {
+            Lookup::Printing { id, .. } => Ok(Resolution::Found(id)),
+            Lookup::Candidates { names, exact } if exact && names.len() == 1 => {
+                Ok(Resolution::Found(names.into_iter().next().unwrap().id))
+            }
+            Lookup::Candidates { names, .. } => {
+                let picked = self
+                    .sorting_ctx
+                    .picks
+                    .get(&pick_key(name))
+                    .filter(|&id| names.iter().any(|c| &c.id == id));
+                match picked {
+                    Some(id) => Ok(Resolution::Found(id.clone())),
+                    None => Ok(Resolution::Ambiguous(names)),
+                }
+            }
+        }
+    }
+
+    /// Asks the user to choose between the candidates for a card, remembering the printing
+    /// chosen for its name
+    pub fn choose(
+        &mut self,
+        name: &str,
+        names: Vec<CardInfo>,
+        undo: bool,
+    ) -> color_eyre::Result<Choice<CardInfo>> {
+        let choice = choose_correct_card(name, names, &mut self.sorting_ctx, undo)?;
+        if let Choice::Chosen(card) = &choice {
+            self.sorting_ctx
+                .picks
+                .insert(pick_key(name), card.id.clone());
+        }
+        Ok(choice)
+    }
+
+    /// Whether a card is named exactly, without asking anything
+    fn is_known(&mut self, card: &CardRef) -> rusqlite::Result<bool> {
+        let (name, set, number) = match *card {
+            CardRef::Id(_) => return Ok(true),
+            CardRef::Name { name, set, number } => (name, set, number),
+        };
+        if let (Some(set), Some(number)) = (set, number) {
+            let printing = self
+                .printing
+                .query_row(rusqlite::params![set, number, self.lang], |_| Ok(()))
+                .optional()?;
+            if printing.is_some() {
+                return Ok(true);
+            }
+        }
+        let (names, exact) = self.candidates(name)?;
+        Ok(exact && !names.is_empty())
+    }
+
+    /// Reads the leading number of an entry as part of the card name when the rest of it names
+    /// no card but the whole of it does
+    pub fn settle<'l>(&mut self, mut entry: Entry<'l>) -> rusqlite::Result<Entry<'l>> {
+        if let Some(uncounted) = entry.uncounted.take() {
+            if !self.is_known(&entry.card)? && self.is_known(&uncounted)? {
+                entry.card = uncounted;
+                entry.count = 1;
+            }
+        }
+        Ok(entry)
+    }
+
+    /// Parses a line of a card list like `parse_line`, settling whether its leading number is a
+    /// count or part of the card name
+    pub fn parse_line<'l>(&mut self, line: &'l str) -> color_eyre::Result<Line<'l>> {
+        Ok(match parse_line(line)? {
+            Line::Card(entry) => Line::Card(self.settle(entry)?),
+            line => line,
+        })
+    }
+
+    /// Finds the scryfall id of a card, asking the user to choose between the candidates if
+    /// there are several of them
+    pub fn resolve(&mut self, card: &CardRef) -> color_eyre::Result<String> {
+        match self.try_resolve(card)? {
+            Resolution::Found(id) => Ok(id),
+            Resolution::Ambiguous(names) => match self.choose(card.name(), names, false)? {
+                Choice::Chosen(card) => Ok(card.id),
+                Choice::Skip | Choice::Undo => Err(eyre!("no card chosen for {}", card.name())),
+            },
+        }
+    }
+
+    /// Finds the oracle identity of a card, only asking the user to choose when the name
+    /// matches several different cards
+    pub fn resolve_oracle(&mut self, card: &CardRef) -> color_eyre::Result<String> {
+        let (name, set, number) = match *card {
+            CardRef::Id(id) => {
+                return self
+                    .oracle
+                    .query_row([id], |row| row.get(0))
+                    .optional()?
+                    .ok_or_else(|| eyre!("unknown card id {}", id))
+            }
+            CardRef::Name { name, set, number } => (name, set, number),
+        };
+
+        match self.lookup(name, set, number)? {
+            Lookup::Printing { oracle, .. } => Ok(oracle),
+            Lookup::Candidates { mut names, exact } => {
+                let mut seen = HashSet::new();
+                names.retain(|c| seen.insert(c.oracle.clone()));
+                match names.len() {
+                    1 if exact => Ok(names.into_iter().next().unwrap().oracle),
+                    _ => match choose_correct_card(name, names, &mut self.sorting_ctx, false)? {
+                        Choice::Chosen(card) => Ok(card.oracle),
+                        Choice::Skip | Choice::Undo => Err(eyre!("no card chosen for {}", name)),
+                    },
+                }
+            }
+        }
+    }
+}
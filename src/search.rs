@@ -0,0 +1,237 @@
+use rusqlite::{Connection, ToSql};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::{
+    filter::PrintingFilter,
+    mana::{self, Cost},
+    price::Currency,
+    resolve, sqlite,
+};
+
+#[derive(StructOpt, Default)]
+pub struct Query {
+    /// Text contained in the card name
+    pub name: String,
+    /// Only cards from the set with this code
+    #[structopt(long)]
+    pub set: Option<String>,
+    /// Only cards present in the collection
+    #[structopt(long)]
+    pub owned: bool,
+    /// Only owned cards with this tag
+    #[structopt(long)]
+    pub tag: Option<String>,
+    /// Only count the copies of this owner
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    pub owner: Option<String>,
+    /// Only cards with a face costing exactly this, like `{2}{W}{W}`
+    #[structopt(long)]
+    pub cost: Option<Cost>,
+    /// Only cards with a face whose cost has at least these symbols
+    #[structopt(long)]
+    pub cost_contains: Option<Cost>,
+    /// Only cards whose cost has a symbol of this kind
+    #[structopt(long, possible_values = mana::KINDS)]
+    pub mana_kind: Option<String>,
+    /// Maximum number of printings to show
+    #[structopt(long, default_value = "50")]
+    pub limit: usize,
+    #[structopt(flatten)]
+    pub printings: PrintingFilter,
+}
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    #[structopt(flatten)]
+    query: Query,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Found {
+    pub id: String,
+    pub name: String,
+    pub set_code: String,
+    pub set_name: String,
+    pub collector_number: String,
+    pub lang: String,
+    pub rarity: String,
+    pub eur: Option<f64>,
+    pub usd: Option<f64>,
+    pub owned: usize,
+    pub owned_foil: usize,
+    pub tags: Vec<String>,
+}
+
+/// Condition keeping the printings with a face whose cost has the symbols of `cost`, exactly
+/// or at least as many of each, along with its parameters
+fn cost_condition(cost: &Cost, exact: bool) -> (String, Vec<(String, usize)>) {
+    let symbols: Vec<_> = cost
+        .0
+        .iter()
+        .map(|(symbol, &amount)| (symbol.to_string(), amount))
+        .collect();
+    let condition = match (symbols.len(), exact) {
+        (0, true) => "scryfall.id IN (SELECT id FROM scryfall_mana WHERE cost = '')".into(),
+        (0, false) => "1".into(),
+        (count, exact) => {
+            let symbol = match exact {
+                true => "(symbol = ? AND amount = ?)",
+                false => "(symbol = ? AND amount >= ?)",
+            };
+            let mut having = format!("SUM({}) = {}", vec![symbol; count].join(" OR "), count);
+            // Faces must not have other symbols either
+            if exact {
+                having.push_str(&format!(" AND COUNT(*) = {}", count));
+            }
+            format!(
+                "scryfall.id IN (SELECT id FROM scryfall_mana_symbols GROUP BY id, face HAVING {})",
+                having
+            )
+        }
+    };
+    (condition, symbols)
+}
+
+/// Searches the printings whose name contains the query, along with the owned copies
+pub fn search(db: &Connection, query: &Query) -> rusqlite::Result<Vec<Found>> {
+    let costs: Vec<_> = [(&query.cost, true), (&query.cost_contains, false)]
+        .iter()
+        .filter_map(|(cost, exact)| cost.as_ref().map(|cost| cost_condition(cost, *exact)))
+        .collect();
+
+    let mut params: Vec<&dyn ToSql> = Vec::new();
+    let (owned, tagged) = match &query.owner {
+        Some(owner) => {
+            params.extend([owner as &dyn ToSql, owner]);
+            (
+                "SELECT id, foil, amount FROM cards WHERE owner = ?",
+                "SELECT id, tag FROM card_tags WHERE owner = ?",
+            )
+        }
+        None => (
+            "SELECT id, foil, amount FROM cards",
+            "SELECT id, tag FROM card_tags",
+        ),
+    };
+
+    let mut conditions =
+        vec!["(scryfall.name LIKE '%' || ? || '%' OR scryfall.printed_name LIKE '%' || ? || '%')"];
+    let name = resolve::fold_name(&query.name);
+    params.extend([&name as &dyn ToSql, &name]);
+    if let Some(set) = &query.set {
+        conditions.push("scryfall.set_code = lower(?)");
+        params.push(set);
+    }
+    let printings = query.printings.condition();
+    conditions.push(&printings);
+    if query.owned {
+        conditions.push("scryfall.id IN (SELECT id FROM owned WHERE amount > 0)");
+    }
+    if let Some(tag) = &query.tag {
+        conditions.push("scryfall.id IN (SELECT id FROM tagged WHERE tag = ?)");
+        params.push(tag);
+    }
+    for (condition, symbols) in &costs {
+        conditions.push(condition);
+        for (symbol, amount) in symbols {
+            params.extend([symbol as &dyn ToSql, amount]);
+        }
+    }
+    if let Some(kind) = &query.mana_kind {
+        conditions.push("scryfall.id IN (SELECT id FROM scryfall_mana_symbols WHERE kind = ?)");
+        params.push(kind);
+    }
+    params.push(&query.limit);
+
+    let statement = format!(
+        r#"
+        WITH owned AS ({}), tagged AS ({})
+        SELECT
+            scryfall.id,
+            scryfall.display_name,
+            scryfall.set_code,
+            scryfall.set_name,
+            scryfall.collector_number,
+            scryfall.lang,
+            scryfall.rarity,
+            CAST(scryfall.eur AS REAL),
+            CAST(scryfall.usd AS REAL),
+            (SELECT IFNULL(SUM(amount), 0) FROM owned WHERE id = scryfall.id AND NOT foil),
+            (SELECT IFNULL(SUM(amount), 0) FROM owned WHERE id = scryfall.id AND foil),
+            -- Tags may hold commas, they are joined with a control character instead
+            (
+                SELECT group_concat(tag, char(31))
+                FROM (SELECT DISTINCT tag FROM tagged WHERE id = scryfall.id)
+            )
+        FROM scryfall
+        WHERE {}
+        ORDER BY scryfall.name, scryfall.set_name, scryfall.collector_number
+        LIMIT ?"#,
+        owned,
+        tagged,
+        conditions.join(" AND ")
+    );
+
+    let found = db
+        .prepare(&statement)?
+        .query_map(params.as_slice(), |row| {
+            Ok(Found {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                set_code: row.get(2)?,
+                set_name: row.get(3)?,
+                collector_number: row.get(4)?,
+                lang: row.get(5)?,
+                rarity: row.get(6)?,
+                eur: row.get(7)?,
+                usd: row.get(8)?,
+                owned: row.get(9)?,
+                owned_foil: row.get(10)?,
+                tags: row
+                    .get::<_, Option<String>>(11)?
+                    .map(|tags| tags.split('\u{1f}').map(Into::into).collect())
+                    .unwrap_or_default(),
+            })
+        })?
+        .collect();
+    found
+}
+
+impl Args {
+    pub fn search(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        for card in search(&db, &self.query)? {
+            let price = match self.currency {
+                Currency::Eur => card.eur,
+                Currency::Usd => card.usd,
+            };
+            print!(
+                "  {} - {} {} ({}, {})",
+                card.name,
+                card.set_code.to_uppercase(),
+                card.collector_number,
+                card.rarity,
+                card.lang
+            );
+            if let Some(price) = price {
+                print!(" {}", self.currency.format(price));
+            }
+            if card.owned + card.owned_foil > 0 {
+                print!(" [{} owned, {} foil]", card.owned, card.owned_foil);
+            }
+            for tag in &card.tags {
+                print!(" #{}", tag);
+            }
+            println!();
+        }
+
+        Ok(())
+    }
+}
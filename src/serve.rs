@@ -0,0 +1,452 @@
+use color_eyre::eyre::eyre;
+use percent_encoding::percent_decode_str;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use structopt::StructOpt;
+
+use crate::{
+    collection::{self, Deal},
+    deck, export,
+    filter::{CardFilter, PrintingFilter},
+    mana,
+    price::Currency,
+    resolve::{CardRef, Line, Resolution, Resolver},
+    search::{self, Query},
+    sqlite, value,
+};
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    #[structopt(long, default_value = "127.0.0.1")]
+    address: String,
+    #[structopt(long, short, default_value = "8080")]
+    port: u16,
+}
+
+/// Answer of the API, independent of the transport
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl ToString) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.to_string() }),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Response {
+    fn from(error: rusqlite::Error) -> Self {
+        Self::error(500, error)
+    }
+}
+
+impl From<color_eyre::Report> for Response {
+    fn from(error: color_eyre::Report) -> Self {
+        Self::error(500, error)
+    }
+}
+
+#[derive(Deserialize)]
+struct CardsBody {
+    /// Lines written like in an add-list file
+    cards: Vec<String>,
+    #[serde(default)]
+    owner: String,
+}
+
+/// JSON API over the collection. Requests are handled in process, the HTTP server only forwards
+/// them, so the API can be driven without any network access.
+pub struct Api {
+    db: Connection,
+}
+
+impl Api {
+    /// The connection needs the spellfix extension loaded
+    pub fn new(db: Connection) -> rusqlite::Result<Self> {
+        sqlite::create_collection_tables(&db)?;
+        Ok(Self { db })
+    }
+
+    pub fn handle(&mut self, method: &str, url: &str, body: &[u8]) -> Response {
+        let (path, query) = match url.find('?') {
+            Some(p) => (&url[..p], parse_query(&url[p + 1..])),
+            None => (url, HashMap::new()),
+        };
+        let segments: Vec<String> = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+            .collect();
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+        let response = match (method, segments.as_slice()) {
+            ("GET", ["cards"]) => self.search(&query),
+            ("GET", ["cards", "lookup"]) => self.lookup(&query),
+            ("GET", ["collection"]) => self.collection(&query),
+            ("POST", ["collection", "add"]) => self.update_collection(body, true),
+            ("POST", ["collection", "remove"]) => self.update_collection(body, false),
+            ("GET", ["value"]) => self.value(&query),
+            ("GET", ["decks"]) => self.decks(),
+            ("GET", ["decks", name, "check"]) => self.check_deck(name, &query),
+            (_, _) => Err(Response::error(
+                404,
+                format!("no route for {} {}", method, path),
+            )),
+        };
+        response.unwrap_or_else(|error| error)
+    }
+
+    fn search(&self, query: &HashMap<String, String>) -> Result<Response, Response> {
+        let mut search = Query {
+            name: query.get("name").cloned().unwrap_or_default(),
+            set: query.get("set").cloned(),
+            owned: flag(query, "owned")?,
+            tag: query.get("tag").cloned(),
+            owner: query.get("owner").cloned(),
+            cost: None,
+            cost_contains: None,
+            mana_kind: query.get("mana_kind").cloned(),
+            limit: 50,
+            printings: PrintingFilter::default(),
+        };
+        if let Some(limit) = query.get("limit") {
+            search.limit = limit
+                .parse()
+                .map_err(|_| Response::error(400, format!("invalid limit {}", limit)))?;
+        }
+        if let Some(kind) = &search.mana_kind {
+            if !mana::KINDS.contains(&kind.as_str()) {
+                return Err(Response::error(400, format!("unknown mana kind {}", kind)));
+            }
+        }
+        for (key, cost) in [
+            ("cost", &mut search.cost),
+            ("cost_contains", &mut search.cost_contains),
+        ] {
+            if let Some(value) = query.get(key) {
+                *cost = Some(value.parse().map_err(|e| Response::error(400, e))?);
+            }
+        }
+        Ok(Response::ok(json!(search::search(&self.db, &search)?)))
+    }
+
+    fn lookup(&self, query: &HashMap<String, String>) -> Result<Response, Response> {
+        let name = required(query, "name")?;
+        let (candidates, exact) =
+            Resolver::new(&self.db, &PrintingFilter::default())?.candidates(name)?;
+        Ok(Response::ok(
+            json!({ "exact": exact, "candidates": candidates }),
+        ))
+    }
+
+    fn collection(&self, query: &HashMap<String, String>) -> Result<Response, Response> {
+        let cards = export::owned_cards(&self.db, &card_filter(query))?;
+        Ok(Response::ok(json!(cards)))
+    }
+
+    /// Adds or removes the cards of a list. Either every line is applied or none of them is.
+    fn update_collection(&mut self, body: &[u8], add: bool) -> Result<Response, Response> {
+        let body: CardsBody = serde_json::from_slice(body)
+            .map_err(|e| Response::error(400, format!("invalid body: {}", e)))?;
+
+        let tx = self.db.transaction()?;
+        let mut changed = Vec::new();
+        {
+            let mut resolver = Resolver::new(&tx, &PrintingFilter::default())?;
+            for line in &body.cards {
+                let entry = match resolver
+                    .parse_line(line)
+                    .map_err(|e| Response::error(400, e))?
+                {
+                    Line::Card(entry) => entry,
+                    Line::Section(_) | Line::Empty => continue,
+                };
+                let id = match resolver.try_resolve(&entry.card)? {
+                    Resolution::Found(id) => id,
+                    Resolution::Ambiguous(candidates) => {
+                        return Err(Response {
+                            status: 409,
+                            body: json!({
+                                "error": format!("{} is ambiguous, give an id or a printing", line),
+                                "line": line,
+                                "candidates": candidates,
+                            }),
+                        })
+                    }
+                };
+                if let CardRef::Id(_) = entry.card {
+                    let exists: bool = tx.query_row(
+                        "SELECT COUNT(*) > 0 FROM scryfall WHERE id = ?1",
+                        [&id],
+                        |row| row.get(0),
+                    )?;
+                    if !exists {
+                        return Err(Response::error(400, format!("unknown card id {}", id)));
+                    }
+                }
+
+                let deal = Deal {
+                    price: entry.price,
+                    date: entry.date,
+                    ..Deal::unknown()
+                };
+                if add {
+                    collection::add_cards(
+                        &tx,
+                        &id,
+                        entry.foil,
+                        entry.count,
+                        &body.owner,
+                        None,
+                        deal,
+                    )?;
+                } else {
+                    collection::remove_cards(&tx, &id, entry.foil, entry.count, &body.owner, deal)
+                        .map_err(|e| Response::error(409, e))?;
+                }
+                changed.push(json!({ "id": id, "foil": entry.foil, "amount": entry.count }));
+            }
+        }
+        tx.commit()?;
+
+        Ok(Response::ok(json!({ "cards": changed })))
+    }
+
+    fn value(&self, query: &HashMap<String, String>) -> Result<Response, Response> {
+        let currency = currency(query)?;
+        let valuation = value::value(&self.db, &card_filter(query), currency)?;
+        Ok(Response::ok(json!(valuation)))
+    }
+
+    fn decks(&self) -> Result<Response, Response> {
+        Ok(Response::ok(json!(deck::decks(&self.db)?)))
+    }
+
+    fn check_deck(
+        &self,
+        name: &str,
+        query: &HashMap<String, String>,
+    ) -> Result<Response, Response> {
+        deck::deck_exists(&self.db, name).map_err(|e| Response::error(404, e))?;
+        let cards = deck::check_deck(
+            &self.db,
+            name,
+            query.get("owner").map(String::as_str),
+            currency(query)?,
+        )?;
+        Ok(Response::ok(json!(cards)))
+    }
+}
+
+/// Decodes a form encoded query component
+fn decode(s: &str) -> String {
+    percent_decode_str(&s.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(p) => (decode(&pair[..p]), decode(&pair[p + 1..])),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+fn required<'q>(query: &'q HashMap<String, String>, key: &str) -> Result<&'q str, Response> {
+    query
+        .get(key)
+        .map(String::as_str)
+        .ok_or_else(|| Response::error(400, format!("missing parameter {}", key)))
+}
+
+fn flag(query: &HashMap<String, String>, key: &str) -> Result<bool, Response> {
+    match query.get(key).map(String::as_str) {
+        None | Some("false") | Some("0") => Ok(false),
+        Some("") | Some("true") | Some("1") => Ok(true),
+        Some(value) => Err(Response::error(
+            400,
+            format!("invalid value {} for {}", value, key),
+        )),
+    }
+}
+
+fn currency(query: &HashMap<String, String>) -> Result<Currency, Response> {
+    match query.get("currency") {
+        None => Ok(Currency::Eur),
+        Some(currency) => currency.parse().map_err(|e| Response::error(400, e)),
+    }
+}
+
+fn card_filter(query: &HashMap<String, String>) -> CardFilter {
+    CardFilter {
+        list: query.get("list").cloned(),
+        location: query.get("location").cloned(),
+        query: query.get("q").cloned(),
+        set: query.get("set").cloned(),
+        tag: query.get("tag").cloned(),
+        owner: query.get("owner").cloned(),
+    }
+}
+
+impl Args {
+    pub fn serve(self) -> color_eyre::Result<()> {
+        let mut api = Api::new(self.database.spellfix_connection()?)?;
+
+        let server = tiny_http::Server::http((self.address.as_str(), self.port))
+            .map_err(|e| eyre!("could not listen on {}:{}: {}", self.address, self.port, e))?;
+        println!("Listening on http://{}:{}", self.address, self.port);
+
+        let content_type =
+            tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+        for mut request in server.incoming_requests() {
+            let mut body = Vec::new();
+            let response = match request.as_reader().read_to_end(&mut body) {
+                Ok(_) => api.handle(request.method().as_str(), request.url(), &body),
+                Err(e) => Response::error(400, e),
+            };
+            // The client may have gone away, which must not stop the server
+            let answered = request.respond(
+                tiny_http::Response::from_string(response.body.to_string())
+                    .with_status_code(response.status)
+                    .with_header(content_type.clone()),
+            );
+            if let Err(e) = answered {
+                eprintln!("could not send a response: {}", e);
+                continue;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Api over an in memory database holding two printings of Lightning Bolt. Plain tables
+    /// stand for the spellfix vocabulary, so only exact names can be looked up.
+    fn api() -> Api {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            r#"
+            CREATE TABLE scryfall (
+                id TEXT PRIMARY KEY NOT NULL, oracle_id TEXT, name TEXT NOT NULL,
+                display_name TEXT NOT NULL, printed_name TEXT, lang TEXT NOT NULL, eur TEXT,
+                eur_foil TEXT, usd TEXT, usd_foil TEXT, uri TEXT NOT NULL, set_code TEXT NOT NULL,
+                set_name TEXT NOT NULL, collector_number TEXT NOT NULL, rarity TEXT NOT NULL,
+                promo BOOLEAN NOT NULL, variation BOOLEAN NOT NULL, layout TEXT NOT NULL,
+                digital BOOLEAN NOT NULL, set_type TEXT NOT NULL, oversized BOOLEAN NOT NULL,
+                booster BOOLEAN NOT NULL, type_line TEXT, oracle_text TEXT,
+                color_identity TEXT NOT NULL, cmc REAL, colors TEXT NOT NULL
+            );
+            CREATE TABLE scryfall_games (id TEXT NOT NULL, game TEXT NOT NULL);
+            CREATE TABLE scryfall_faces (id TEXT NOT NULL, face INTEGER NOT NULL, name TEXT NOT NULL, display_name TEXT NOT NULL);
+            CREATE TABLE scryfall_mana (id TEXT NOT NULL, face INTEGER NOT NULL, cost TEXT NOT NULL);
+            CREATE TABLE card_names (word TEXT, score INTEGER);
+            CREATE TABLE face_names (word TEXT, score INTEGER);
+            INSERT INTO scryfall VALUES
+                ('bolt-m10', 'bolt', 'lightning bolt', 'Lightning Bolt', NULL, 'en', '1.50', NULL,
+                    NULL, NULL, '', 'm10', 'Magic 2010', '146', 'common', 0, 0, 'normal', 0,
+                    'core', 0, 1, 'Instant', '', 'R', 1, 'R'),
+                ('bolt-m11', 'bolt', 'lightning bolt', 'Lightning Bolt', NULL, 'en', '1.20', NULL,
+                    NULL, NULL, '', 'm11', 'Magic 2011', '149', 'common', 0, 0, 'normal', 0,
+                    'core', 0, 1, 'Instant', '', 'R', 1, 'R');
+            INSERT INTO scryfall_games VALUES ('bolt-m10', 'paper'), ('bolt-m11', 'paper');
+            INSERT INTO scryfall_mana VALUES ('bolt-m10', 0, '{R}'), ('bolt-m11', 0, '{R}');
+            "#,
+        )
+        .unwrap();
+        Api::new(db).unwrap()
+    }
+
+    fn post(api: &mut Api, url: &str, cards: &[&str]) -> Response {
+        api.handle(
+            "POST",
+            url,
+            json!({ "cards": cards }).to_string().as_bytes(),
+        )
+    }
+
+    fn owned(api: &mut Api) -> Value {
+        let response = api.handle("GET", "/collection", b"");
+        assert_eq!(response.status, 200);
+        response.body
+    }
+
+    #[test]
+    fn unknown_route() {
+        let response = api().handle("GET", "/nothing/here", b"");
+        assert_eq!(response.status, 404);
+        assert_eq!(api().handle("DELETE", "/cards", b"").status, 404);
+    }
+
+    #[test]
+    fn bad_parameters() {
+        let mut api = api();
+        assert_eq!(api.handle("GET", "/cards?owned=maybe", b"").status, 400);
+        assert_eq!(api.handle("GET", "/cards?limit=many", b"").status, 400);
+        assert_eq!(api.handle("GET", "/cards?cost=RR", b"").status, 400);
+        assert_eq!(api.handle("GET", "/value?currency=btc", b"").status, 400);
+        assert_eq!(api.handle("GET", "/cards/lookup", b"").status, 400);
+        assert_eq!(api.handle("POST", "/collection/add", b"{").status, 400);
+    }
+
+    #[test]
+    fn ambiguous_add_changes_nothing() {
+        let mut api = api();
+        let response = post(
+            &mut api,
+            "/collection/add",
+            &["2 Lightning Bolt (M10) 146", "1 Lightning Bolt"],
+        );
+        assert_eq!(response.status, 409);
+        assert_eq!(response.body["line"], "1 Lightning Bolt");
+        assert_eq!(response.body["candidates"].as_array().unwrap().len(), 2);
+        assert_eq!(owned(&mut api), json!([]));
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let mut api = api();
+        let response = post(&mut api, "/collection/add", &["3 Lightning Bolt (M10) 146"]);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["cards"][0]["id"], "bolt-m10");
+        let cards = owned(&mut api);
+        assert_eq!(cards[0]["id"], "bolt-m10");
+        assert_eq!(cards[0]["amount"], 3);
+
+        let response = post(
+            &mut api,
+            "/collection/remove",
+            &["2 Lightning Bolt (M10) 146"],
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(owned(&mut api)[0]["amount"], 1);
+
+        // Copies that are not owned cannot be removed
+        let response = post(
+            &mut api,
+            "/collection/remove",
+            &["2 Lightning Bolt (M10) 146"],
+        );
+        assert_eq!(response.status, 409);
+        assert_eq!(owned(&mut api)[0]["amount"], 1);
+    }
+}
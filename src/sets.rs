@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+use crate::{price::Currency, sqlite};
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Count the foil cards separately
+    #[structopt(long)]
+    foils: bool,
+    /// Count the cards flagged as variations
+    #[structopt(long)]
+    include_variations: bool,
+    /// Count the promotional cards
+    #[structopt(long)]
+    include_promos: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// List the cards of a set that are not owned, with their price
+    Missing {
+        code: String,
+        #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+        currency: Currency,
+    },
+}
+
+const RARITIES: &[&str] = &["common", "uncommon", "rare", "mythic", "special", "bonus"];
+
+#[derive(Default)]
+struct Completion {
+    total: usize,
+    owned: usize,
+    foil: usize,
+}
+
+impl Args {
+    /// Condition on the `scryfall` table selecting the counted printings
+    fn printings(&self) -> String {
+        let mut conditions = vec!["1"];
+        if !self.include_promos {
+            conditions.push("NOT scryfall.promo");
+        }
+        if !self.include_variations {
+            conditions.push("NOT scryfall.variation");
+        }
+        conditions.join(" AND ")
+    }
+
+    /// Condition on the owned cards counted as a copy of a collector number
+    fn owned(&self) -> &'static str {
+        match self.foils {
+            true => "NOT cards.foil",
+            false => "1",
+        }
+    }
+
+    pub fn sets(self) -> color_eyre::Result<()> {
+        match self.command {
+            None => self.completion(),
+            Some(Command::Missing { ref code, currency }) => self.missing(code, currency),
+        }
+    }
+
+    fn completion(&self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let mut statement = db.prepare(&format!(
+            r#"
+            WITH printings AS (
+                SELECT DISTINCT set_code, set_name, collector_number, rarity
+                FROM scryfall
+                WHERE {printings}
+            ),
+            owned AS (
+                SELECT
+                    scryfall.set_code,
+                    scryfall.collector_number,
+                    MAX({owned}) AS regular,
+                    MAX(cards.foil) AS foil
+                FROM cards JOIN scryfall ON scryfall.id = cards.id
+                WHERE cards.amount > 0 AND {printings}
+                GROUP BY scryfall.set_code, scryfall.collector_number
+            )
+            SELECT
+                printings.set_code,
+                printings.set_name,
+                printings.rarity,
+                COUNT(*),
+                IFNULL(SUM(owned.regular), 0),
+                IFNULL(SUM(owned.foil), 0)
+            FROM
+                printings
+                LEFT JOIN owned
+                    ON owned.set_code = printings.set_code
+                    AND owned.collector_number = printings.collector_number
+            WHERE printings.set_code IN (SELECT set_code FROM owned)
+            GROUP BY printings.set_code, printings.rarity
+            ORDER BY printings.set_name"#,
+            printings = self.printings(),
+            owned = self.owned(),
+        ))?;
+
+        let mut sets: BTreeMap<(String, String), BTreeMap<String, Completion>> = BTreeMap::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let completion = Completion {
+                total: row.get(3)?,
+                owned: row.get(4)?,
+                foil: row.get(5)?,
+            };
+            sets.entry((row.get(1)?, row.get(0)?))
+                .or_default()
+                .insert(row.get(2)?, completion);
+        }
+
+        for ((name, code), rarities) in sets {
+            let mut total = Completion::default();
+            for completion in rarities.values() {
+                total.total += completion.total;
+                total.owned += completion.owned;
+                total.foil += completion.foil;
+            }
+            print!(
+                "{} ({}): {}/{} ({}%)",
+                name,
+                code.to_uppercase(),
+                total.owned,
+                total.total,
+                total.owned * 100 / total.total
+            );
+            if self.foils {
+                print!(", foil {}/{}", total.foil, total.total);
+            }
+            println!();
+
+            let mut rarities: Vec<_> = rarities.into_iter().collect();
+            rarities.sort_by_key(|(rarity, _)| {
+                RARITIES
+                    .iter()
+                    .position(|r| r == rarity)
+                    .unwrap_or(RARITIES.len())
+            });
+            for (rarity, completion) in rarities {
+                print!("  {}: {}/{}", rarity, completion.owned, completion.total);
+                if self.foils {
+                    print!(", foil {}/{}", completion.foil, completion.total);
+                }
+                println!();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn missing(&self, code: &str, currency: Currency) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let owned = match self.foils {
+            true => "cards.foil",
+            false => "1",
+        };
+        let mut statement = db.prepare(&format!(
+            r#"
+            SELECT
+                scryfall.collector_number,
+                MIN(scryfall.display_name),
+                MIN(scryfall.rarity),
+                MIN({price})
+            FROM scryfall
+            WHERE
+                scryfall.set_code = lower(?1)
+                AND {printings}
+                AND NOT EXISTS (
+                    SELECT 1 FROM cards JOIN scryfall AS owned ON owned.id = cards.id
+                    WHERE
+                        owned.set_code = scryfall.set_code
+                        AND owned.collector_number = scryfall.collector_number
+                        AND cards.amount > 0
+                        AND {owned}
+                )
+            GROUP BY scryfall.collector_number
+            ORDER BY CAST(scryfall.collector_number AS INTEGER), scryfall.collector_number"#,
+            price = currency.price_expr(if self.foils { "1" } else { "0" }),
+            printings = self.printings(),
+            owned = owned,
+        ))?;
+
+        let mut total = 0.;
+        let mut count = 0;
+        let mut rows = statement.query([code])?;
+        while let Some(row) = rows.next()? {
+            let number: String = row.get(0)?;
+            let name: String = row.get(1)?;
+            let rarity: String = row.get(2)?;
+            let price: Option<f64> = row.get(3)?;
+            count += 1;
+            total += price.unwrap_or(0.);
+            println!(
+                "  #{} {} ({}): {}",
+                number,
+                name,
+                rarity,
+                price
+                    .map(|p| currency.format(p))
+                    .unwrap_or_else(|| "no price".into())
+            );
+        }
+        println!(
+            "{} cards missing{}, costing {}",
+            count,
+            if self.foils { " in foil" } else { "" },
+            currency.format(total)
+        );
+
+        Ok(())
+    }
+}
@@ -17,3 +17,245 @@ impl Args {
         Ok(connection)
     }
 }
+
+// Database arguments for commands that never touch the spellfix vocabulary
+#[derive(StructOpt)]
+pub struct Database {
+    #[structopt(short, long)]
+    pub database: PathBuf,
+}
+
+impl Database {
+    pub fn connection(&self) -> Result<Connection, rusqlite::Error> {
+        Connection::open(&self.database)
+    }
+}
+
+/// Creates the tables holding the user collection, decks, wants and preferences if they don't
+/// exist yet
+pub fn create_collection_tables(db: &Connection) -> Result<(), rusqlite::Error> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS cards (
+            id TEXT NOT NULL,
+            foil BOOLEAN NOT NULL DEFAULT false,
+            owner TEXT NOT NULL DEFAULT '',
+            amount INTEGER NOT NULL,
+            PRIMARY KEY (id, foil, owner)
+        );
+        CREATE TABLE IF NOT EXISTS lists (hash BLOB PRIMARY KEY NOT NULL);
+        CREATE TABLE IF NOT EXISTS list_cards (
+            hash BLOB NOT NULL,
+            id TEXT NOT NULL,
+            foil BOOLEAN NOT NULL DEFAULT false,
+            amount INTEGER NOT NULL,
+            PRIMARY KEY (hash, id, foil)
+        );
+        CREATE TABLE IF NOT EXISTS decks (name TEXT PRIMARY KEY NOT NULL, format TEXT);
+        CREATE TABLE IF NOT EXISTS deck_cards (
+            deck TEXT NOT NULL,
+            id TEXT NOT NULL,
+            board TEXT NOT NULL,
+            amount INTEGER NOT NULL,
+            PRIMARY KEY (deck, id, board)
+        );
+        CREATE TABLE IF NOT EXISTS wants (
+            want INTEGER PRIMARY KEY,
+            oracle TEXT NOT NULL,
+            printing TEXT,
+            amount INTEGER NOT NULL,
+            max_price REAL,
+            currency TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS preferred_printings (
+            name TEXT PRIMARY KEY NOT NULL,
+            id TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS preferred_sets (set_code TEXT PRIMARY KEY NOT NULL);
+        CREATE TABLE IF NOT EXISTS acquisitions (
+            acquisition INTEGER PRIMARY KEY,
+            id TEXT NOT NULL,
+            foil BOOLEAN NOT NULL,
+            hash BLOB,
+            amount INTEGER NOT NULL,
+            bought INTEGER NOT NULL,
+            price REAL,
+            currency TEXT NOT NULL,
+            date TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS disposals (
+            disposal INTEGER PRIMARY KEY,
+            acquisition INTEGER,
+            id TEXT NOT NULL,
+            foil BOOLEAN NOT NULL,
+            amount INTEGER NOT NULL,
+            price REAL,
+            currency TEXT NOT NULL,
+            date TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS exchanges (
+            exchange INTEGER PRIMARY KEY,
+            kind TEXT NOT NULL,
+            counterparty TEXT,
+            reason TEXT,
+            cash REAL,
+            currency TEXT NOT NULL,
+            date TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS products (
+            product INTEGER PRIMARY KEY,
+            kind TEXT NOT NULL,
+            set_code TEXT NOT NULL,
+            price REAL,
+            currency TEXT NOT NULL,
+            date TEXT NOT NULL,
+            slots TEXT,
+            expected REAL
+        );
+        CREATE TABLE IF NOT EXISTS card_tags (
+            id TEXT NOT NULL,
+            foil BOOLEAN NOT NULL,
+            owner TEXT NOT NULL DEFAULT '',
+            tag TEXT NOT NULL,
+            amount INTEGER NOT NULL,
+            PRIMARY KEY (id, foil, owner, tag)
+        );
+        CREATE TABLE IF NOT EXISTS card_notes (
+            note INTEGER PRIMARY KEY,
+            id TEXT NOT NULL,
+            foil BOOLEAN NOT NULL,
+            owner TEXT NOT NULL DEFAULT '',
+            text TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS loans (
+            loan INTEGER PRIMARY KEY,
+            id TEXT NOT NULL,
+            foil BOOLEAN NOT NULL,
+            owner TEXT NOT NULL,
+            borrower TEXT NOT NULL,
+            amount INTEGER NOT NULL,
+            date TEXT NOT NULL,
+            due TEXT,
+            returned TEXT
+        );
+        CREATE TABLE IF NOT EXISTS cached_images (
+            id TEXT NOT NULL,
+            face INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            uri TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            PRIMARY KEY (id, face, kind)
+        );
+        "#,
+    )?;
+    add_column(db, "lists", "name", "TEXT")?;
+    add_column(db, "lists", "location", "TEXT")?;
+    add_column(db, "lists", "product", "INTEGER")?;
+    add_column(db, "acquisitions", "exchange", "INTEGER")?;
+    add_column(db, "disposals", "exchange", "INTEGER")?;
+    add_column(db, "lists", "owner", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(db, "acquisitions", "owner", "TEXT NOT NULL DEFAULT ''")?;
+    if !has_column(db, "cards", "owner")? {
+        // The owner is part of the primary key, so the table has to be rebuilt
+        db.execute_batch(
+            r#"
+            SAVEPOINT cards_owner;
+            CREATE TABLE cards_owner (
+                id TEXT NOT NULL,
+                foil BOOLEAN NOT NULL DEFAULT false,
+                owner TEXT NOT NULL DEFAULT '',
+                amount INTEGER NOT NULL,
+                PRIMARY KEY (id, foil, owner)
+            );
+            INSERT INTO cards_owner (id, foil, amount) SELECT id, foil, amount FROM cards;
+            DROP TABLE cards;
+            ALTER TABLE cards_owner RENAME TO cards;
+            RELEASE cards_owner;
+            "#,
+        )?;
+    }
+    // Tags and notes made before owners were tracked go to the owner having the most copies
+    if !has_column(db, "card_tags", "owner")? {
+        db.execute_batch(
+            r#"
+            SAVEPOINT card_tags_owner;
+            CREATE TABLE card_tags_owner (
+                id TEXT NOT NULL,
+                foil BOOLEAN NOT NULL,
+                owner TEXT NOT NULL DEFAULT '',
+                tag TEXT NOT NULL,
+                amount INTEGER NOT NULL,
+                PRIMARY KEY (id, foil, owner, tag)
+            );
+            INSERT INTO card_tags_owner (id, foil, owner, tag, amount)
+            SELECT id, foil, IFNULL((
+                SELECT owner FROM cards WHERE cards.id = card_tags.id AND cards.foil = card_tags.foil
+                ORDER BY amount DESC LIMIT 1
+            ), ''), tag, amount FROM card_tags;
+            DROP TABLE card_tags;
+            ALTER TABLE card_tags_owner RENAME TO card_tags;
+            RELEASE card_tags_owner;
+            "#,
+        )?;
+    }
+    if !has_column(db, "card_notes", "owner")? {
+        add_column(db, "card_notes", "owner", "TEXT NOT NULL DEFAULT ''")?;
+        db.execute(
+            r#"UPDATE card_notes SET owner = IFNULL((
+                SELECT owner FROM cards WHERE cards.id = card_notes.id AND cards.foil = card_notes.foil
+                ORDER BY amount DESC LIMIT 1
+            ), '')"#,
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn has_table(db: &Connection, table: &str) -> Result<bool, rusqlite::Error> {
+    db.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [table],
+        |row| row.get(0),
+    )
+}
+
+pub fn has_column(db: &Connection, table: &str, column: &str) -> Result<bool, rusqlite::Error> {
+    db.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |row| row.get(0),
+    )
+}
+
+/// Adds a column to a table created by an older version, if it is missing
+pub fn add_column(
+    db: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), rusqlite::Error> {
+    if !has_column(db, table, column)? {
+        db.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Creates the table remembering the identity of every printing ever dumped, so that
+/// collection entries can be matched again once scryfall drops or replaces an id
+pub fn create_history_table(db: &Connection) -> Result<(), rusqlite::Error> {
+    db.execute(
+        r#"
+        CREATE TABLE IF NOT EXISTS scryfall_history (
+            id TEXT PRIMARY KEY NOT NULL,
+            oracle_id TEXT,
+            lang TEXT NOT NULL,
+            set_code TEXT NOT NULL,
+            collector_number TEXT NOT NULL
+        )"#,
+        [],
+    )?;
+    Ok(())
+}
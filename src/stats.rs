@@ -0,0 +1,255 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+use crate::{deck, filter::CardFilter, legality, mana::Cost, resolve::Board, sqlite};
+
+/// Card types counted in the type distribution
+const TYPES: &[&str] = &[
+    "Creature",
+    "Instant",
+    "Sorcery",
+    "Artifact",
+    "Enchantment",
+    "Planeswalker",
+    "Battle",
+    "Land",
+];
+
+/// Highest mana value having its own column in the curve, the next one gathers the others
+const CURVE_TOP: usize = 6;
+
+/// Length of the longest bar of the curve
+const BAR_WIDTH: usize = 40;
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    /// Describe this saved deck instead of the owned cards
+    #[structopt(long, conflicts_with_all = &["list", "location", "owner", "query", "set", "tag"])]
+    deck: Option<String>,
+    #[structopt(flatten)]
+    filter: CardFilter,
+    /// Print the report as JSON
+    #[structopt(long)]
+    json: bool,
+}
+
+#[derive(Serialize, Default, Debug)]
+struct Report {
+    cards: usize,
+    lands: usize,
+    /// Average mana value of the cards that are not lands
+    average_mana_value: f64,
+    /// Number of cards that are not lands for each mana value, the last one counting the
+    /// cards costing more
+    curve: Vec<usize>,
+    /// Number of cards of each color, `C` counting the colorless cards that are not lands
+    colors: BTreeMap<String, usize>,
+    /// Number of mana symbols of each color in the costs
+    pips: BTreeMap<String, usize>,
+    types: BTreeMap<String, usize>,
+    /// Only given for decks
+    suggested_lands: Option<LandSuggestion>,
+}
+
+#[derive(Serialize, Debug)]
+struct LandSuggestion {
+    lands: usize,
+    /// Lands producing each color, following the share of its symbols
+    sources: BTreeMap<String, usize>,
+}
+
+impl Report {
+    fn new(db: &Connection, cards: &[(String, usize)], deck: bool) -> rusqlite::Result<Self> {
+        let mut statement = db.prepare(
+            r#"
+            SELECT
+                IFNULL(type_line, ''),
+                IFNULL(cmc, 0),
+                colors,
+                (SELECT cost FROM scryfall_mana WHERE scryfall_mana.id = scryfall.id ORDER BY face LIMIT 1)
+            FROM scryfall
+            WHERE id = ?1"#,
+        )?;
+
+        let mut report = Report {
+            curve: vec![0; CURVE_TOP + 2],
+            ..Default::default()
+        };
+        let mut mana_value = 0.;
+        for (id, count) in cards {
+            let card = statement
+                .query_row([id], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, f64>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                })
+                .optional()?;
+            let (type_line, cmc, colors, cost) = match card {
+                Some(card) => card,
+                None => continue,
+            };
+            let count = *count;
+            report.cards += count;
+
+            let front = type_line.split(" // ").next().unwrap_or_default();
+            let front = front.split(" — ").next().unwrap_or_default();
+            for kind in TYPES {
+                if front.split(' ').any(|word| word == *kind) {
+                    *report.types.entry(kind.to_string()).or_default() += count;
+                }
+            }
+            if front.split(' ').any(|word| word == "Land") {
+                report.lands += count;
+                continue;
+            }
+
+            mana_value += cmc * count as f64;
+            report.curve[(cmc as usize).min(CURVE_TOP + 1)] += count;
+            if colors.is_empty() {
+                *report.colors.entry("C".into()).or_default() += count;
+            }
+            for color in colors.chars() {
+                *report.colors.entry(color.to_string()).or_default() += count;
+            }
+            if let Some(Ok(cost)) = cost.map(|cost| cost.parse::<Cost>()) {
+                for (color, pips) in cost.pips() {
+                    *report.pips.entry(color.to_string()).or_default() += pips * count;
+                }
+            }
+        }
+
+        let spells = report.cards - report.lands;
+        if spells > 0 {
+            report.average_mana_value = mana_value / spells as f64;
+        }
+        if deck {
+            report.suggested_lands = Some(report.suggest_lands());
+        }
+        Ok(report)
+    }
+
+    /// Number of lands following Frank Karsten's formula for 60 card decks, scaled to the size
+    /// of the deck
+    fn suggest_lands(&self) -> LandSuggestion {
+        let lands =
+            (self.cards as f64 * (19.59 + 1.90 * self.average_mana_value) / 60.).round() as usize;
+        let pips: usize = self.pips.values().sum();
+        let sources = self
+            .pips
+            .iter()
+            .map(|(color, &count)| {
+                let share = (lands * count) as f64 / pips as f64;
+                (color.clone(), share.round() as usize)
+            })
+            .collect();
+        LandSuggestion { lands, sources }
+    }
+
+    fn print(&self, title: &str) {
+        println!(
+            "{}: {} cards, {} lands, average mana value {:.2}",
+            title, self.cards, self.lands, self.average_mana_value
+        );
+
+        println!();
+        println!("Mana curve:");
+        let highest = self.curve.iter().copied().max().unwrap_or(0).max(1);
+        let width = highest.min(BAR_WIDTH);
+        for (value, &count) in self.curve.iter().enumerate() {
+            let value = match value {
+                v if v > CURVE_TOP => format!("{}+", v),
+                v => v.to_string(),
+            };
+            let bar = "#".repeat((count * width).div_ceil(highest));
+            println!("  {:<3}|{:<width$} {}", value, bar, count, width = width);
+        }
+
+        println!();
+        println!("Colors:    cards   pips");
+        for color in "WUBRGC".chars().map(String::from) {
+            let cards = self.colors.get(&color).copied().unwrap_or(0);
+            let pips = self.pips.get(&color).copied().unwrap_or(0);
+            if cards > 0 || pips > 0 {
+                println!("  {:<8}{:>6}{:>7}", color, cards, pips);
+            }
+        }
+
+        println!();
+        println!("Types:");
+        for kind in TYPES {
+            if let Some(count) = self.types.get(*kind) {
+                println!("  {:<14}{:>4}", kind, count);
+            }
+        }
+
+        if let Some(suggestion) = &self.suggested_lands {
+            println!();
+            print!(
+                "Lands: {}, about {} suggested",
+                self.lands, suggestion.lands
+            );
+            let sources: Vec<_> = "WUBRGC"
+                .chars()
+                .map(String::from)
+                .filter_map(|color| {
+                    suggestion
+                        .sources
+                        .get(&color)
+                        .map(|count| format!("{} {}", count, color))
+                })
+                .collect();
+            if !sources.is_empty() {
+                print!(" ({})", sources.join(", "));
+            }
+            println!();
+        }
+    }
+}
+
+impl Args {
+    pub fn stats(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let cards: Vec<(String, usize)> = match &self.deck {
+            Some(name) => {
+                deck::deck_exists(&db, name)?;
+                // The sideboard is not played
+                legality::saved_deck(&db, name)?
+                    .into_iter()
+                    .filter(|(_, board, _)| *board != Board::Side)
+                    .map(|(id, _, count)| (id, count))
+                    .collect()
+            }
+            None => {
+                let (query, params) = self.filter.owned_cards();
+                let mut statement = db.prepare(&format!(
+                    "SELECT id, SUM(amount) FROM ({}) GROUP BY id",
+                    query
+                ))?;
+                let rows = statement
+                    .query_map(rusqlite::params_from_iter(params.iter()), |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?;
+                rows.collect::<Result<_, _>>()?
+            }
+        };
+
+        let report = Report::new(&db, &cards, self.deck.is_some())?;
+        if self.json {
+            serde_json::to_writer_pretty(std::io::stdout().lock(), &report)?;
+            println!();
+        } else {
+            report.print(self.deck.as_deref().unwrap_or("Collection"));
+        }
+
+        Ok(())
+    }
+}
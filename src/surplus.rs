@@ -0,0 +1,185 @@
+use rusqlite::Connection;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use crate::{
+    export::{self, ExportedCard, Format},
+    filter::CardFilter,
+    price::Currency,
+    sqlite,
+};
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    #[structopt(flatten)]
+    filter: CardFilter,
+    /// Number of copies of each card to keep
+    #[structopt(long, short, default_value = "4")]
+    playset: usize,
+    /// Only keep a single copy of each card
+    #[structopt(long, conflicts_with = "playset")]
+    commander: bool,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+    /// Export the surplus as a trade binder list instead of showing a report
+    #[structopt(long, short, possible_values = Format::VARIANTS)]
+    format: Option<Format>,
+    /// File to write the export to, defaults to the standard output
+    #[structopt(long, short, requires = "format")]
+    output: Option<PathBuf>,
+}
+
+/// A printing of a card, with the price of a single copy
+type Printing = (ExportedCard, Option<f64>);
+
+/// Copies of a card owned beyond the playset, all printings taken together
+pub struct Surplus {
+    pub name: String,
+    pub owned: usize,
+    /// Surplus copies of each printing
+    pub printings: Vec<Printing>,
+}
+
+impl Surplus {
+    pub fn amount(&self) -> usize {
+        self.printings.iter().map(|(card, _)| card.amount).sum()
+    }
+
+    pub fn value(&self) -> f64 {
+        self.printings
+            .iter()
+            .map(|(card, price)| price.unwrap_or(0.) * card.amount as f64)
+            .sum()
+    }
+}
+
+/// Finds the cards owned more than `playset` times. The most valuable copies are kept, the
+/// surplus is made of the cheapest ones. The result is sorted by decreasing value.
+pub fn surplus(
+    db: &Connection,
+    filter: &CardFilter,
+    playset: usize,
+    currency: Currency,
+) -> color_eyre::Result<Vec<Surplus>> {
+    let (owned, params) = filter.owned_cards();
+    let query = format!(
+        r#"
+        SELECT
+            IFNULL(scryfall.oracle_id, scryfall.id) AS oracle,
+            scryfall.display_name,
+            scryfall.set_code,
+            scryfall.set_name,
+            scryfall.collector_number,
+            filtered.foil,
+            filtered.amount,
+            scryfall.id,
+            {price} AS price
+        FROM ({owned}) AS filtered JOIN scryfall ON scryfall.id = filtered.id
+        ORDER BY oracle, price IS NOT NULL, price"#,
+        price = currency.price_expr("filtered.foil"),
+        owned = owned
+    );
+
+    let mut statement = db.prepare(&query)?;
+    let mut rows = statement.query(rusqlite::params_from_iter(params.iter()))?;
+
+    let mut groups: Vec<(String, Vec<Printing>)> = Vec::new();
+    while let Some(row) = rows.next()? {
+        let oracle: String = row.get(0)?;
+        let card = ExportedCard {
+            name: row.get(1)?,
+            set_code: row.get(2)?,
+            set_name: row.get(3)?,
+            collector_number: row.get(4)?,
+            foil: row.get(5)?,
+            amount: row.get(6)?,
+            id: row.get(7)?,
+            tags: String::new(),
+        };
+        let price = row.get(8)?;
+        match groups.last_mut() {
+            Some((o, printings)) if *o == oracle => printings.push((card, price)),
+            _ => groups.push((oracle, vec![(card, price)])),
+        }
+    }
+
+    let mut surplus: Vec<Surplus> = groups
+        .into_iter()
+        .filter_map(|(_, printings)| {
+            let owned: usize = printings.iter().map(|(card, _)| card.amount).sum();
+            let mut extra = owned.checked_sub(playset).filter(|&e| e > 0)?;
+            let name = printings[0].0.name.clone();
+            let printings = printings
+                .into_iter()
+                .filter_map(|(mut card, price)| {
+                    card.amount = card.amount.min(extra);
+                    extra -= card.amount;
+                    Some((card, price)).filter(|(card, _)| card.amount > 0)
+                })
+                .collect();
+            Some(Surplus {
+                name,
+                owned,
+                printings,
+            })
+        })
+        .collect();
+
+    surplus.sort_by(|a, b| {
+        b.value()
+            .partial_cmp(&a.value())
+            .unwrap()
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    Ok(surplus)
+}
+
+impl Args {
+    pub fn surplus(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let playset = if self.commander { 1 } else { self.playset };
+        let surplus = surplus(&db, &self.filter, playset, self.currency)?;
+
+        if let Some(format) = self.format {
+            let cards: Vec<ExportedCard> = surplus
+                .into_iter()
+                .flat_map(|s| s.printings.into_iter().map(|(card, _)| card))
+                .collect();
+            return export::write_output(format, &cards, self.output.as_deref());
+        }
+
+        println!("Cards owned more than {} times:", playset);
+        for card in &surplus {
+            println!(
+                "  {} {} ({} owned): {} - {}",
+                card.amount(),
+                card.name,
+                card.owned,
+                self.currency.format(card.value()),
+                card.printings
+                    .iter()
+                    .map(|(p, _)| format!(
+                        "{} {}{} x{}",
+                        p.set_code.to_uppercase(),
+                        p.collector_number,
+                        if p.foil { " foil" } else { "" },
+                        p.amount
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        println!(
+            "Total: {}",
+            self.currency
+                .format(surplus.iter().map(Surplus::value).sum())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use color_eyre::eyre::eyre;
+use rusqlite::Connection;
+use structopt::StructOpt;
+
+use crate::{
+    collection,
+    filter::PrintingFilter,
+    resolve::{Line, Resolver},
+    sqlite,
+};
+
+#[derive(StructOpt)]
+pub enum Args {
+    /// Tag owned copies of a card
+    Add(TagArgs),
+    /// Remove a tag from copies of a card
+    Remove(TagArgs),
+    /// Attach a note to the owned copies of a card
+    Note(NoteArgs),
+    /// Show the tags with their number of copies, or the cards having a tag
+    Show(ShowArgs),
+}
+
+#[derive(StructOpt)]
+pub struct TagArgs {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    /// Card written like a line of an add-list file (`2 [F] Lightning Bolt (M10) 146`)
+    card: String,
+    #[structopt(required = true)]
+    tags: Vec<String>,
+    /// Every owned copy, instead of the number given in the line
+    #[structopt(long)]
+    all: bool,
+    /// Whose copies are tagged
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    owner: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct NoteArgs {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    /// Card written like a line of an add-list file
+    card: String,
+    /// Text of the note, the notes of the card are removed when there is none
+    text: Option<String>,
+    /// Whose copies the note is about
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    owner: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct ShowArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    tag: Option<String>,
+    /// Only the copies of this owner
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    owner: Option<String>,
+}
+
+/// Resolves a card line to a printing of the owner, returning its id, foil flag and count along
+/// with the number of copies the owner has
+fn owned_card(
+    db: &Connection,
+    card: &str,
+    owner: &str,
+) -> color_eyre::Result<(String, bool, usize, usize)> {
+    let mut resolver = Resolver::new(db, &PrintingFilter::default())?;
+    let entry = match resolver.parse_line(card)? {
+        Line::Card(entry) => entry,
+        Line::Empty | Line::Section(_) => return Err(eyre!("{} is not a card", card)),
+    };
+    let id = resolver.resolve(&entry.card)?;
+    let owned: usize = db.query_row(
+        "SELECT IFNULL(SUM(amount), 0) FROM cards WHERE id = ?1 AND foil = ?2 AND owner = ?3",
+        rusqlite::params![id, entry.foil, owner],
+        |row| row.get(0),
+    )?;
+    if owned == 0 {
+        return Err(eyre!(
+            "{} is not owned{}",
+            card,
+            match owner {
+                "" => String::new(),
+                owner => format!(" by {}", owner),
+            }
+        ));
+    }
+    Ok((id, entry.foil, entry.count, owned))
+}
+
+impl Args {
+    pub fn tags(self) -> color_eyre::Result<()> {
+        match self {
+            Args::Add(args) => args.add(),
+            Args::Remove(args) => args.remove(),
+            Args::Note(args) => args.note(),
+            Args::Show(args) => args.show(),
+        }
+    }
+}
+
+impl TagArgs {
+    fn add(self) -> color_eyre::Result<()> {
+        let db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let owner = self.owner.as_deref().unwrap_or_default();
+        let (id, foil, count, owned) = owned_card(&db, &self.card, owner)?;
+        let count = if self.all { owned } else { count };
+        collection::tag_cards(&db, &id, foil, count, owner, &self.tags)?;
+
+        Ok(())
+    }
+
+    fn remove(self) -> color_eyre::Result<()> {
+        let db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let owner = self.owner.as_deref().unwrap_or_default();
+        let (id, foil, count, _) = owned_card(&db, &self.card, owner)?;
+        let count = if self.all { None } else { Some(count) };
+        for tag in &self.tags {
+            if collection::untag_cards(&db, &id, foil, count, owner, tag)? == 0 {
+                println!("{} was not tagged {}", self.card, tag);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl NoteArgs {
+    fn note(self) -> color_eyre::Result<()> {
+        let db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let owner = self.owner.as_deref().unwrap_or_default();
+        let (id, foil, _, _) = owned_card(&db, &self.card, owner)?;
+        match &self.text {
+            Some(text) => db.execute(
+                "INSERT INTO card_notes (id, foil, owner, text) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![id, foil, owner, text],
+            )?,
+            None => db.execute(
+                "DELETE FROM card_notes WHERE id = ?1 AND foil = ?2 AND owner = ?3",
+                rusqlite::params![id, foil, owner],
+            )?,
+        };
+
+        Ok(())
+    }
+}
+
+impl ShowArgs {
+    fn show(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let tag = match self.tag {
+            Some(tag) => tag,
+            None => {
+                let mut statement = db.prepare(
+                    r#"SELECT tag, SUM(amount) FROM card_tags WHERE ?1 IS NULL OR owner = ?1
+                    GROUP BY tag ORDER BY tag"#,
+                )?;
+                let mut rows = statement.query([&self.owner])?;
+                while let Some(row) = rows.next()? {
+                    let tag: String = row.get(0)?;
+                    let amount: usize = row.get(1)?;
+                    println!("  {}: {} cards", tag, amount);
+                }
+                return Ok(());
+            }
+        };
+
+        let mut statement = db.prepare(
+            r#"
+            SELECT
+                card_tags.amount,
+                scryfall.display_name,
+                upper(scryfall.set_code) || ' ' || scryfall.collector_number,
+                card_tags.foil,
+                (
+                    SELECT group_concat(text, '; ') FROM card_notes
+                    WHERE card_notes.id = card_tags.id AND card_notes.foil = card_tags.foil
+                        AND card_notes.owner = card_tags.owner
+                ),
+                card_tags.owner
+            FROM card_tags JOIN scryfall ON scryfall.id = card_tags.id
+            WHERE card_tags.tag = ?1 AND (?2 IS NULL OR card_tags.owner = ?2)
+            ORDER BY scryfall.name, scryfall.set_code, scryfall.collector_number, card_tags.owner"#,
+        )?;
+        let mut rows = statement.query(rusqlite::params![tag, self.owner])?;
+        while let Some(row) = rows.next()? {
+            let amount: usize = row.get(0)?;
+            let name: String = row.get(1)?;
+            let printing: String = row.get(2)?;
+            let foil: bool = row.get(3)?;
+            let notes: Option<String> = row.get(4)?;
+            let owner: String = row.get(5)?;
+            println!(
+                "  {} {} ({}){}{}{}",
+                amount,
+                name,
+                printing,
+                if foil { " [F]" } else { "" },
+                match (&self.owner, owner.as_str()) {
+                    (None, owner) if !owner.is_empty() => format!(" [{}]", owner),
+                    _ => String::new(),
+                },
+                notes.map(|n| format!(" - {}", n)).unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use crate::{
+    add_list::{self, Outcome},
+    collection::{self, Deal},
+    filter::PrintingFilter,
+    price::Currency,
+    resolve::Resolver,
+    sqlite,
+};
+
+/// Difference between the two sides under which a trade is considered fair
+const FAIR: f64 = 0.05;
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    /// Cards given, written like in an add-list file
+    given: PathBuf,
+    /// Cards received, written like in an add-list file
+    received: PathBuf,
+    /// Who the trade is made with
+    #[structopt(long = "with")]
+    counterparty: String,
+    /// Money received along with the cards, negative when paid (`--cash=-5`)
+    #[structopt(long, allow_hyphen_values = true)]
+    cash: Option<f64>,
+    /// Date of the trade, as YYYY-MM-DD
+    #[structopt(long, parse(try_from_str = collection::parse_date))]
+    date: Option<String>,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+    /// Only show whether the trade is fair, without changing the collection
+    #[structopt(long)]
+    dry_run: bool,
+    /// Neither use nor remember the printings chosen in previous runs
+    #[structopt(long)]
+    ignore_prefs: bool,
+    /// Whose collection the trade is made from
+    #[structopt(long, env = "MTG_DB_OWNER")]
+    owner: Option<String>,
+}
+
+/// A card of one side of the trade
+struct Traded {
+    id: String,
+    foil: bool,
+    count: usize,
+    /// Price written in the list for each copy
+    price: Option<f64>,
+}
+
+/// Current value of one side of the trade, along with the number of cards without a price
+fn worth(
+    db: &rusqlite::Connection,
+    cards: &[Traded],
+    currency: Currency,
+) -> rusqlite::Result<(f64, usize)> {
+    let mut worth = 0.;
+    let mut unpriced = 0;
+    for card in cards {
+        match collection::market_price(db, &card.id, card.foil, currency)? {
+            Some(price) => worth += price * card.count as f64,
+            None => unpriced += card.count,
+        }
+    }
+    Ok((worth, unpriced))
+}
+
+/// Prices of each copy of the cards of one side, sharing what is left of `total` once the cards
+/// with a price of their own are paid between the other ones
+fn prices(
+    db: &rusqlite::Connection,
+    cards: &[Traded],
+    total: f64,
+    currency: Currency,
+) -> rusqlite::Result<Vec<f64>> {
+    let unpriced: Vec<_> = cards
+        .iter()
+        .filter(|card| card.price.is_none())
+        .map(|card| (card.id.as_str(), card.foil, card.count))
+        .collect();
+    let priced: f64 = cards
+        .iter()
+        .filter_map(|card| card.price.map(|price| price * card.count as f64))
+        .sum();
+    let total = (total - priced).max(0.);
+    let mut shared = match unpriced.is_empty() {
+        true => Vec::new(),
+        false => collection::share_price(db, total, currency, &unpriced)?,
+    }
+    .into_iter();
+    Ok(cards
+        .iter()
+        .map(|card| card.price.or_else(|| shared.next()).unwrap_or(0.))
+        .collect())
+}
+
+impl Args {
+    fn read_side(resolver: &mut Resolver, list: &PathBuf) -> color_eyre::Result<Vec<Traded>> {
+        let list = std::fs::read_to_string(list)?;
+        let lines: Vec<&str> = list.lines().collect();
+        let mut outcomes = Vec::new();
+        add_list::resolve_lines(resolver, &lines, &mut outcomes)?;
+
+        let mut cards = Vec::new();
+        for (line, outcome) in lines.iter().zip(outcomes) {
+            match outcome {
+                Outcome::Card {
+                    id,
+                    foil,
+                    count,
+                    price,
+                    ..
+                } => cards.push(Traded {
+                    id,
+                    foil,
+                    count,
+                    price,
+                }),
+                Outcome::Skipped => println!("Skipped {}", line),
+                Outcome::Ignored => (),
+            }
+        }
+        Ok(cards)
+    }
+
+    pub fn trade(self) -> color_eyre::Result<()> {
+        let mut db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let tx = db.transaction()?;
+        {
+            let mut resolver =
+                Resolver::with_prefs(&tx, &PrintingFilter::default(), self.ignore_prefs)?;
+            let given = Self::read_side(&mut resolver, &self.given)?;
+            let received = Self::read_side(&mut resolver, &self.received)?;
+            resolver.save_prefs()?;
+
+            let cash = self.cash.unwrap_or(0.);
+            let (given_worth, given_unpriced) = worth(&tx, &given, self.currency)?;
+            let (received_worth, received_unpriced) = worth(&tx, &received, self.currency)?;
+            let count = |cards: &[Traded]| cards.iter().map(|c| c.count).sum::<usize>();
+
+            println!(
+                "Giving {} cards worth {}",
+                count(&given),
+                self.currency.format(given_worth)
+            );
+            print!(
+                "Receiving {} cards worth {}",
+                count(&received),
+                self.currency.format(received_worth)
+            );
+            match cash {
+                c if c > 0. => println!(" and {}", self.currency.format(c)),
+                c if c < 0. => println!(" for {}", self.currency.format(-c)),
+                _ => println!(),
+            }
+            if given_unpriced + received_unpriced > 0 {
+                println!(
+                    "{} cards have no price and are not counted",
+                    given_unpriced + received_unpriced
+                );
+            }
+
+            let difference = received_worth + cash - given_worth;
+            let largest = given_worth.max(received_worth + cash);
+            if difference.abs() <= FAIR * largest {
+                println!("The trade is fair by current prices");
+            } else if difference > 0. {
+                println!(
+                    "You receive {} more than you give ({:.0}%)",
+                    self.currency.format(difference),
+                    100. * difference / largest
+                );
+            } else {
+                println!(
+                    "You give {} more than you receive ({:.0}%)",
+                    self.currency.format(-difference),
+                    100. * -difference / largest
+                );
+            }
+
+            if self.dry_run {
+                return Ok(());
+            }
+
+            let deal = Deal {
+                currency: self.currency,
+                date: self.date.as_deref(),
+                ..Deal::unknown()
+            };
+            let exchange = collection::record_exchange(
+                &tx,
+                "trade",
+                Some(&self.counterparty),
+                None,
+                self.cash,
+                deal,
+            )?;
+            let deal = Deal {
+                exchange: Some(exchange),
+                ..deal
+            };
+
+            // Each side is sold for the value of the other one
+            let owner = self.owner.as_deref().unwrap_or_default();
+            let given_prices = prices(&tx, &given, (received_worth + cash).max(0.), self.currency)?;
+            for (card, price) in given.iter().zip(given_prices) {
+                let deal = Deal {
+                    price: Some(price),
+                    ..deal
+                };
+                collection::remove_cards(&tx, &card.id, card.foil, card.count, owner, deal)?;
+            }
+            let received_prices =
+                prices(&tx, &received, (given_worth - cash).max(0.), self.currency)?;
+            for (card, price) in received.iter().zip(received_prices) {
+                let deal = Deal {
+                    price: Some(price),
+                    ..deal
+                };
+                collection::add_cards(&tx, &card.id, card.foil, card.count, owner, None, deal)?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::{filter::CardFilter, price::Currency, sqlite};
+
+#[derive(StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    #[structopt(flatten)]
+    filter: CardFilter,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Valuation {
+    pub cards: usize,
+    pub value: f64,
+    /// Cards without a known price
+    pub unpriced: usize,
+}
+
+/// Computes the value of the owned cards matching the filter at the current prices
+pub fn value(
+    db: &Connection,
+    filter: &CardFilter,
+    currency: Currency,
+) -> rusqlite::Result<Valuation> {
+    let (owned, params) = filter.owned_cards();
+    let query = format!(
+        r#"
+        SELECT
+            IFNULL(SUM(filtered.amount), 0),
+            IFNULL(SUM(filtered.amount * {price}), 0),
+            IFNULL(SUM(IIF({price} IS NULL, filtered.amount, 0)), 0)
+        FROM ({owned}) AS filtered JOIN scryfall ON scryfall.id = filtered.id"#,
+        price = currency.price_expr("filtered.foil"),
+        owned = owned
+    );
+
+    db.query_row(&query, rusqlite::params_from_iter(params.iter()), |row| {
+        Ok(Valuation {
+            cards: row.get(0)?,
+            value: row.get(1)?,
+            unpriced: row.get(2)?,
+        })
+    })
+}
+
+impl Args {
+    pub fn value(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let valuation = value(&db, &self.filter, self.currency)?;
+        print!(
+            "{} cards worth {}",
+            valuation.cards,
+            self.currency.format(valuation.value)
+        );
+        if valuation.unpriced > 0 {
+            print!(" ({} cards without a price)", valuation.unpriced);
+        }
+        println!();
+
+        Ok(())
+    }
+}
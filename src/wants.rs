@@ -0,0 +1,361 @@
+use color_eyre::eyre::eyre;
+use rusqlite::{Connection, OptionalExtension};
+use structopt::StructOpt;
+
+use crate::{
+    deck,
+    filter::PrintingFilter,
+    price::Currency,
+    resolve::{self, CardRef, Line, Resolver},
+    sqlite,
+};
+
+#[derive(StructOpt)]
+pub enum Args {
+    /// Add a card to the want list, written like an add-list line (`2 Lightning Bolt`). The
+    /// amount is the number of copies wanted in the whole collection.
+    Add(AddArgs),
+    /// Add the cards missing from a deck to the want list
+    FromDeck(FromDeckArgs),
+    /// Show the want list
+    List(ListArgs),
+    /// Remove an entry of the want list
+    Remove(RemoveArgs),
+    /// Show the wants under their maximum price and the wants covered by the collection
+    Report(ListArgs),
+}
+
+#[derive(StructOpt)]
+pub struct AddArgs {
+    #[structopt(flatten)]
+    database: sqlite::Args,
+    card: String,
+    /// The card is given by its oracle id
+    #[structopt(long)]
+    oracle: bool,
+    /// Only want the chosen printing of the card
+    #[structopt(long)]
+    printing: bool,
+    #[structopt(long, short)]
+    max_price: Option<f64>,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+    /// Neither use nor remember the printings chosen in previous runs
+    #[structopt(long)]
+    ignore_prefs: bool,
+}
+
+#[derive(StructOpt)]
+pub struct FromDeckArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    deck: String,
+    #[structopt(long, short)]
+    max_price: Option<f64>,
+    #[structopt(long, short, default_value = "eur", possible_values = Currency::VARIANTS)]
+    currency: Currency,
+}
+
+#[derive(StructOpt)]
+pub struct ListArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+}
+
+#[derive(StructOpt)]
+pub struct RemoveArgs {
+    #[structopt(flatten)]
+    database: sqlite::Database,
+    want: i64,
+}
+
+pub struct Want {
+    pub want: i64,
+    pub name: String,
+    /// Set code and collector number of the wanted printing
+    pub printing: Option<String>,
+    pub amount: usize,
+    pub max_price: Option<f64>,
+    pub currency: Currency,
+    /// Current price of the cheapest matching printing
+    pub price: Option<f64>,
+    pub owned: usize,
+}
+
+impl Want {
+    pub fn under_price(&self) -> bool {
+        matches!((self.price, self.max_price), (Some(price), Some(max)) if price <= max)
+    }
+
+    pub fn covered(&self) -> bool {
+        self.owned >= self.amount
+    }
+
+    fn print(&self) {
+        print!(
+            "  #{} {} {}{}",
+            self.want,
+            self.amount,
+            self.name,
+            self.printing
+                .as_ref()
+                .map(|p| format!(" ({})", p))
+                .unwrap_or_default()
+        );
+        match self.price {
+            Some(price) => print!(": {}", self.currency.format(price)),
+            None => print!(": no price"),
+        }
+        if let Some(max) = self.max_price {
+            print!(" (max {})", self.currency.format(max));
+        }
+        println!(", {} owned", self.owned);
+    }
+}
+
+pub fn wants(db: &Connection) -> color_eyre::Result<Vec<Want>> {
+    // Wants are priced in their own currency
+    let price = format!(
+        "IIF(wants.currency = '{usd}', {usd_price}, {eur_price})",
+        usd = Currency::Usd.as_str(),
+        usd_price = Currency::Usd.price_expr("false"),
+        eur_price = Currency::Eur.price_expr("false")
+    );
+    let mut statement = db.prepare(&format!(
+        r#"
+        SELECT
+            wants.want,
+            (
+                SELECT display_name FROM scryfall
+                WHERE oracle_id = wants.oracle OR id = wants.oracle
+                LIMIT 1
+            ) AS name,
+            (
+                SELECT upper(set_code) || ' ' || collector_number FROM scryfall
+                WHERE id = wants.printing
+            ),
+            wants.amount,
+            wants.max_price,
+            wants.currency,
+            IIF(
+                wants.printing IS NULL,
+                (
+                    SELECT MIN({price}) FROM scryfall
+                    WHERE oracle_id = wants.oracle OR id = wants.oracle
+                ),
+                (
+                    SELECT {price} FROM scryfall
+                    WHERE id = wants.printing
+                )
+            ),
+            IIF(
+                wants.printing IS NULL,
+                (
+                    SELECT IFNULL(SUM(cards.amount), 0)
+                    FROM cards JOIN scryfall ON scryfall.id = cards.id
+                    WHERE scryfall.oracle_id = wants.oracle OR scryfall.id = wants.oracle
+                ),
+                (SELECT IFNULL(SUM(amount), 0) FROM cards WHERE id = wants.printing)
+            )
+        FROM wants
+        ORDER BY name, wants.want"#,
+        price = price
+    ))?;
+
+    let mut rows = statement.query([])?;
+    let mut wants = Vec::new();
+    while let Some(row) = rows.next()? {
+        let currency: String = row.get(5)?;
+        wants.push(Want {
+            want: row.get(0)?,
+            name: row
+                .get::<_, Option<String>>(1)?
+                .unwrap_or_else(|| "<unknown card>".into()),
+            printing: row.get(2)?,
+            amount: row.get(3)?,
+            max_price: row.get(4)?,
+            currency: currency.parse()?,
+            price: row.get(6)?,
+            owned: row.get(7)?,
+        });
+    }
+
+    Ok(wants)
+}
+
+impl Args {
+    pub fn wants(self) -> color_eyre::Result<()> {
+        match self {
+            Args::Add(args) => args.add(),
+            Args::FromDeck(args) => args.add_missing(),
+            Args::List(args) => args.list(),
+            Args::Remove(args) => args.remove(),
+            Args::Report(args) => args.report(),
+        }
+    }
+}
+
+impl AddArgs {
+    fn add(self) -> color_eyre::Result<()> {
+        let db = self.database.spellfix_connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let entry = match resolve::parse_line(&self.card)? {
+            Line::Card(entry) => entry,
+            _ => return Err(eyre!("{} is not a card", self.card)),
+        };
+
+        let (oracle, printing, count) = if self.oracle {
+            let oracle = entry.card.name().to_string();
+            let exists: bool = db.query_row(
+                "SELECT COUNT(*) > 0 FROM scryfall WHERE oracle_id = ?1",
+                [&oracle],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                return Err(eyre!("unknown oracle id {}", oracle));
+            }
+            (oracle, None, entry.count)
+        } else {
+            let mut resolver =
+                Resolver::with_prefs(&db, &PrintingFilter::default(), self.ignore_prefs)?;
+            let entry = resolver.settle(entry)?;
+            let wants_printing = match entry.card {
+                CardRef::Id(_) => true,
+                CardRef::Name { number, .. } => self.printing || number.is_some(),
+            };
+            let result = if wants_printing {
+                let id = resolver.resolve(&entry.card)?;
+                let oracle = resolver.resolve_oracle(&CardRef::Id(&id))?;
+                (oracle, Some(id), entry.count)
+            } else {
+                (resolver.resolve_oracle(&entry.card)?, None, entry.count)
+            };
+            resolver.save_prefs()?;
+            result
+        };
+
+        db.execute(
+            r#"INSERT INTO wants (oracle, printing, amount, max_price, currency)
+            VALUES (?1, ?2, ?3, ?4, ?5)"#,
+            rusqlite::params![
+                oracle,
+                printing,
+                count,
+                self.max_price,
+                self.currency.as_str()
+            ],
+        )?;
+
+        Ok(())
+    }
+}
+
+impl FromDeckArgs {
+    fn add_missing(self) -> color_eyre::Result<()> {
+        let mut db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let missing: Vec<_> = deck::check_deck(&db, &self.deck, None, self.currency)?
+            .into_iter()
+            .filter(|card| card.missing() > 0)
+            .collect();
+        // Cards missing from the database have no oracle id to want
+        let (unknown, missing): (Vec<_>, Vec<_>) = missing.into_iter().partition(|c| c.unknown);
+
+        // Wanted amounts count the copies in the whole collection, so the deck needs all of them
+        let tx = db.transaction()?;
+        for card in &missing {
+            let existing: Option<i64> = tx
+                .query_row(
+                    "SELECT want FROM wants WHERE oracle = ?1 AND printing IS NULL",
+                    [&card.oracle],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match existing {
+                Some(want) => {
+                    tx.execute(
+                        "UPDATE wants SET amount = MAX(amount, ?2) WHERE want = ?1",
+                        rusqlite::params![want, card.needed],
+                    )?;
+                }
+                None => {
+                    tx.execute(
+                        r#"INSERT INTO wants (oracle, amount, max_price, currency)
+                        VALUES (?1, ?2, ?3, ?4)"#,
+                        rusqlite::params![
+                            card.oracle,
+                            card.needed,
+                            self.max_price,
+                            self.currency.as_str()
+                        ],
+                    )?;
+                }
+            }
+            println!(
+                "  {} {} ({} missing)",
+                card.needed,
+                card.name,
+                card.missing()
+            );
+        }
+        tx.commit()?;
+
+        println!("Added {} cards to the want list", missing.len());
+        for card in unknown {
+            println!(
+                "Skipped {}, it is missing from the card database",
+                card.name
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl ListArgs {
+    fn list(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        for want in wants(&db)? {
+            want.print();
+        }
+
+        Ok(())
+    }
+
+    fn report(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let wants = wants(&db)?;
+
+        println!("Wants under their maximum price:");
+        for want in wants.iter().filter(|w| w.under_price() && !w.covered()) {
+            want.print();
+        }
+
+        println!("Wants covered by the collection:");
+        for want in wants.iter().filter(|w| w.covered()) {
+            want.print();
+        }
+
+        Ok(())
+    }
+}
+
+impl RemoveArgs {
+    fn remove(self) -> color_eyre::Result<()> {
+        let db = self.database.connection()?;
+        sqlite::create_collection_tables(&db)?;
+
+        let removed = db.execute("DELETE FROM wants WHERE want = ?1", [self.want])?;
+        if removed == 0 {
+            return Err(eyre!("there is no want #{}", self.want));
+        }
+
+        Ok(())
+    }
+}